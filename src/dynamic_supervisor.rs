@@ -0,0 +1,150 @@
+use std::fmt::Debug;
+use std::io;
+
+use crate::handle::{ChildCount, ChildInfo, HandleError, SupervisorHandle};
+use crate::intensity::RestartIntensity;
+use crate::observer::SupervisorObserver;
+use crate::spawn::Backend;
+use crate::supervisor::Supervisor;
+use crate::worker::Worker;
+use crate::worker::backoff_policy::BackoffPolicy;
+use crate::worker::restartable::RestartPolicy;
+use crate::worker::shutdown_policy::ShutdownPolicy;
+
+/// The name a dynamic supervisor's template is added to its supervisor under.
+const TEMPLATE: &str = "dynamic";
+
+/// Represents a supervisor whose children aren't declared up front, but are
+/// started at runtime from a single template, for example one worker per
+/// tenant or per accepted connection.
+///
+/// Like Erlang/OTP's `DynamicSupervisor`, each child is restarted on its own
+/// according to its restart and backoff policies, and is removed once it's
+/// given up on. Children are started and terminated through a
+/// [`DynamicSupervisorHandle`].
+pub struct DynamicSupervisor {
+    supervisor: Supervisor,
+}
+
+impl Debug for DynamicSupervisor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicSupervisor")
+            .field("supervisor", &self.supervisor)
+            .finish()
+    }
+}
+
+impl DynamicSupervisor {
+    pub(crate) fn new(supervisor: Supervisor) -> Self {
+        // children that are given up on are removed, making room for others
+        Self {
+            supervisor: supervisor.with_remove_stopped(),
+        }
+    }
+
+    pub(crate) fn into_supervisor(self) -> Supervisor {
+        self.supervisor
+    }
+
+    /// Sets the template children are built from, given the ID they're
+    /// started with.
+    pub fn with_template<W, F>(mut self, template: F) -> Self
+    where
+        W: Worker + 'static,
+        F: Fn(&str) -> W + Send + 'static,
+    {
+        self.supervisor = self.supervisor.add_template(TEMPLATE, template);
+        self
+    }
+
+    /// Limits how many children the DynamicSupervisor may have at once. By
+    /// default, there's no limit.
+    pub fn with_max_children(mut self, max_children: usize) -> Self {
+        self.supervisor = self.supervisor.with_max_children(max_children);
+        self
+    }
+
+    /// Sets the backoff policy for the DynamicSupervisor.
+    pub fn with_backoff_policy(mut self, backoff_policy: BackoffPolicy) -> Self {
+        self.supervisor = self.supervisor.with_backoff_policy(backoff_policy);
+        self
+    }
+
+    /// Sets the restart policy for the DynamicSupervisor. Its children are
+    /// lost when it's restarted.
+    pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.supervisor = self.supervisor.with_restart_policy(restart_policy);
+        self
+    }
+
+    /// Sets the restart intensity for the DynamicSupervisor.
+    pub fn with_restart_intensity(mut self, intensity: RestartIntensity) -> Self {
+        self.supervisor = self.supervisor.with_restart_intensity(intensity);
+        self
+    }
+
+    /// Sets the observer notified when the DynamicSupervisor's children start,
+    /// stop and are restarted.
+    pub fn with_observer(mut self, observer: impl SupervisorObserver + 'static) -> Self {
+        self.supervisor = self.supervisor.with_observer(observer);
+        self
+    }
+
+    /// Sets how the DynamicSupervisor starts the watcher running its
+    /// children, like [`Supervisor::with_backend()`].
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.supervisor = self.supervisor.with_backend(backend);
+        self
+    }
+
+    /// Sets the shutdown policy for the DynamicSupervisor.
+    pub fn with_shutdown_policy(mut self, shutdown_policy: ShutdownPolicy) -> Self {
+        self.supervisor = self.supervisor.with_shutdown_policy(shutdown_policy);
+        self
+    }
+
+    /// Returns a handle for starting and terminating the DynamicSupervisor's
+    /// children. Without a handle, the DynamicSupervisor stops as soon as it's
+    /// started.
+    pub fn handle(&mut self) -> io::Result<DynamicSupervisorHandle> {
+        Ok(DynamicSupervisorHandle {
+            handle: self.supervisor.handle()?,
+        })
+    }
+}
+
+/// A handle for starting and terminating a [`DynamicSupervisor`]'s children,
+/// which can be cloned and used from any thread or process in the tree.
+#[derive(Debug, Clone)]
+pub struct DynamicSupervisorHandle {
+    handle: SupervisorHandle,
+}
+
+impl DynamicSupervisorHandle {
+    /// Starts a new child with the ID from the template.
+    pub fn start_child(&self, id: &str) -> Result<(), HandleError> {
+        self.handle.start_child(TEMPLATE, id)
+    }
+
+    /// Stops the child according to its shutdown policy and removes it,
+    /// returning once it has stopped.
+    pub fn terminate_child(&self, id: &str) -> Result<(), HandleError> {
+        self.handle.terminate_child(id)?;
+        self.handle.delete_child(id)
+    }
+
+    /// Lists the children, in their start order.
+    pub fn which_children(&self) -> Result<Vec<ChildInfo>, HandleError> {
+        self.handle.which_children()
+    }
+
+    /// Counts the children.
+    pub fn count_children(&self) -> Result<ChildCount, HandleError> {
+        self.handle.count_children()
+    }
+
+    /// Stops the children, after which the DynamicSupervisor stops.
+    pub fn shutdown(&self) -> Result<(), HandleError> {
+        self.handle.shutdown()
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::Display;
+use std::io;
+
+use log::debug;
+
+use crate::ipc;
+
+/// The destination of the frames reporting the errors of child processes up
+/// the tree, which can't be the path of a worker, since names can't contain
+/// tabs.
+pub(crate) const DESTINATION: &str = "\terrors";
+
+/// Represents an error that stopped a supervisor from running its children.
+///
+/// An error in the process the tree was started from is returned by
+/// [`Supertree::start()`](crate::Supertree::start), or by
+/// [`SupertreeHandle::wait()`](crate::SupertreeHandle::wait). An error in a
+/// forked child process is reported to the process the tree was started
+/// from, and listed by [`ExitSummary::errors()`](crate::ExitSummary::errors),
+/// while the child exits as having failed and is restarted under its restart
+/// policy.
+#[derive(Debug)]
+pub enum SupertreeError {
+    /// Forking or re-executing the process for the child with the path
+    /// failed.
+    Fork(String, io::Error),
+    /// Waiting for the children of the supervisor with the path failed.
+    Waitpid(String, io::Error),
+    /// Building the runtime for the workers of the supervisor with the path
+    /// failed.
+    Runtime(String, io::Error),
+    /// Installing the signal handlers of the supervisor with the path, or of
+    /// the watcher running its workers, failed, or creating the socket that
+    /// a tree spawned on a runtime is stopped on in their place did.
+    Signals(String, io::Error),
+    /// The process the supervisor with the path was started from runs the
+    /// number of threads, which could hold locks that its forked children
+    /// would deadlock on. See [`ThreadPolicy`](crate::ThreadPolicy).
+    Multithreaded(String, usize),
+}
+
+impl SupertreeError {
+    /// Returns the path of the child or supervisor that failed.
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Fork(path, _)
+            | Self::Waitpid(path, _)
+            | Self::Runtime(path, _)
+            | Self::Signals(path, _)
+            | Self::Multithreaded(path, _) => path,
+        }
+    }
+
+    fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::Fork(_, err)
+            | Self::Waitpid(_, err)
+            | Self::Runtime(_, err)
+            | Self::Signals(_, err) => Some(err),
+            Self::Multithreaded(..) => None,
+        }
+    }
+
+    /// Reports the error to the parent process group, from a child process
+    /// that's about to exit because of it.
+    pub(crate) fn report(&self) {
+        let result = ipc::encode_frame(DESTINATION, &self.encode()).and_then(|frame| {
+            let uplink = ipc::uplink().ok_or(ipc::IpcError::Disconnected)?;
+            uplink.send(&frame).map_err(ipc::IpcError::Io)
+        });
+        if let Err(err) = result {
+            debug!("couldn't report error err={err}");
+        }
+    }
+
+    /// Encodes the error as its variant, the OS error code if there is one
+    /// or the number of threads, the length of the path, the path and then
+    /// the error's message.
+    fn encode(&self) -> Vec<u8> {
+        let variant = match self {
+            Self::Fork(..) => 0,
+            Self::Waitpid(..) => 1,
+            Self::Runtime(..) => 2,
+            Self::Signals(..) => 3,
+            Self::Multithreaded(..) => 4,
+        };
+        let (value, message) = match self {
+            Self::Multithreaded(_, threads) => (*threads as i32, String::new()),
+            _ => {
+                let err = self.io_error().unwrap();
+                (err.raw_os_error().unwrap_or(-1), err.to_string())
+            }
+        };
+        let path = self.path();
+        let mut buf = Vec::with_capacity(7 + path.len() + message.len());
+        buf.push(variant);
+        buf.extend_from_slice(&value.to_be_bytes());
+        buf.extend_from_slice(&(path.len() as u16).to_be_bytes());
+        buf.extend_from_slice(path.as_bytes());
+        buf.extend_from_slice(message.as_bytes());
+        buf
+    }
+
+    /// Decodes an error reported by a child process, if it's well formed.
+    pub(crate) fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 7 {
+            return None;
+        }
+        let (header, rest) = buf.split_at(7);
+        let value = i32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let len = u16::from_be_bytes([header[5], header[6]]) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (path, message) = rest.split_at(len);
+        let path = std::str::from_utf8(path).ok()?.to_string();
+        let err = if value >= 0 {
+            io::Error::from_raw_os_error(value)
+        } else {
+            io::Error::other(String::from_utf8_lossy(message))
+        };
+        match header[0] {
+            0 => Some(Self::Fork(path, err)),
+            1 => Some(Self::Waitpid(path, err)),
+            2 => Some(Self::Runtime(path, err)),
+            3 => Some(Self::Signals(path, err)),
+            4 => Some(Self::Multithreaded(path, value as usize)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SupertreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fork(path, err) => write!(f, "couldn't start child {path}: {err}"),
+            Self::Waitpid(path, err) => {
+                write!(f, "couldn't wait for the children of {path}: {err}")
+            }
+            Self::Runtime(path, err) => {
+                write!(f, "couldn't build the runtime for {path}: {err}")
+            }
+            Self::Signals(path, err) => {
+                write!(f, "couldn't install signal handlers for {path}: {err}")
+            }
+            Self::Multithreaded(path, threads) => {
+                write!(
+                    f,
+                    "couldn't start {path} from a process with {threads} threads"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SupertreeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.io_error().map(|err| err as _)
+    }
+}
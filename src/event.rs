@@ -0,0 +1,386 @@
+use std::fmt::{Debug, Display};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::observer::{ChildExit, SupervisorObserver};
+use crate::{ExitStatus, ipc, signal};
+
+/// The destination of the frames carrying events up the tree, which can't be
+/// the path of a worker, since names can't contain tabs.
+pub(crate) const DESTINATION: &str = "\tevents";
+
+/// The length of an encoded event, before the path of the child.
+const HEADER_LEN: usize = 1 + 4 + 12 + 5 + 12;
+
+/// Represents what happened to a child of a supervisor.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SupervisionEventKind {
+    /// The child started, including each time it's restarted.
+    Started,
+    /// The child stopped with the exit status, which is the exit code its
+    /// reason maps to for a worker.
+    Exited(ExitStatus),
+    /// The child is to be restarted after the delay.
+    RestartScheduled(Duration),
+    /// The child failed with the exit status, and won't be restarted under its
+    /// restart policy.
+    GaveUp(ExitStatus),
+    /// The supervisor's children were restarted more often than its restart
+    /// intensity allows, so it's stopping them and exiting.
+    IntensityExceeded,
+    /// The supervisor was asked to shut down, and is stopping its children.
+    Shutdown,
+}
+
+/// A change in the state of a child of a supervisor in the tree, received by
+/// the process the tree was started from through [`SupervisionEvents`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SupervisionEvent {
+    kind: SupervisionEventKind,
+    path: String,
+    pid: u32,
+    time: SystemTime,
+}
+
+impl SupervisionEvent {
+    /// Returns what happened.
+    pub fn kind(&self) -> SupervisionEventKind {
+        self.kind
+    }
+
+    /// Returns the path of the child the event is about, or of the supervisor
+    /// itself for
+    /// [`IntensityExceeded`](SupervisionEventKind::IntensityExceeded)
+    /// and [`Shutdown`](SupervisionEventKind::Shutdown) events.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the pid of the process the child ran in, which is the watcher
+    /// process for a worker, or of the supervisor's own process for events
+    /// about the supervisor.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Returns when the event happened.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Returns how the child stopped, for events about a child that stopped.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        match self.kind {
+            SupervisionEventKind::Exited(exit_status)
+            | SupervisionEventKind::GaveUp(exit_status) => Some(exit_status),
+            _ => None,
+        }
+    }
+
+    /// Returns when the child is due to be restarted, for
+    /// [`RestartScheduled`](SupervisionEventKind::RestartScheduled) events.
+    pub fn restart_at(&self) -> Option<SystemTime> {
+        match self.kind {
+            SupervisionEventKind::RestartScheduled(delay) => Some(self.time + delay),
+            _ => None,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let (kind, exit_status, delay) = match self.kind {
+            SupervisionEventKind::Started => (0, None, Duration::ZERO),
+            SupervisionEventKind::Exited(exit_status) => (1, Some(exit_status), Duration::ZERO),
+            SupervisionEventKind::RestartScheduled(delay) => (2, None, delay),
+            SupervisionEventKind::GaveUp(exit_status) => (3, Some(exit_status), Duration::ZERO),
+            SupervisionEventKind::IntensityExceeded => (4, None, Duration::ZERO),
+            SupervisionEventKind::Shutdown => (5, None, Duration::ZERO),
+        };
+        let (status, value) = match exit_status {
+            None => (0, 0),
+            Some(ExitStatus::Exited(code)) => (1, code),
+            Some(ExitStatus::Signaled {
+                signal,
+                core_dumped,
+            }) => (2 + core_dumped as u8, signal),
+        };
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.path.len());
+        buf.push(kind);
+        buf.extend_from_slice(&self.pid.to_be_bytes());
+        buf.extend_from_slice(&time.as_secs().to_be_bytes());
+        buf.extend_from_slice(&time.subsec_nanos().to_be_bytes());
+        buf.push(status);
+        buf.extend_from_slice(&value.to_be_bytes());
+        buf.extend_from_slice(&delay.as_secs().to_be_bytes());
+        buf.extend_from_slice(&delay.subsec_nanos().to_be_bytes());
+        buf.extend_from_slice(self.path.as_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        let (header, path) = buf.split_at(HEADER_LEN);
+        let u32_at = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_be_bytes(header[at..at + 8].try_into().unwrap());
+        let value = u32_at(18) as i32;
+        let exit_status = match header[17] {
+            0 => None,
+            1 => Some(ExitStatus::Exited(value)),
+            2 | 3 => Some(ExitStatus::Signaled {
+                signal: value,
+                core_dumped: header[17] == 3,
+            }),
+            _ => return None,
+        };
+        let delay = Duration::new(u64_at(22), u32_at(30));
+        let kind = match (header[0], exit_status) {
+            (0, None) => SupervisionEventKind::Started,
+            (1, Some(exit_status)) => SupervisionEventKind::Exited(exit_status),
+            (2, None) => SupervisionEventKind::RestartScheduled(delay),
+            (3, Some(exit_status)) => SupervisionEventKind::GaveUp(exit_status),
+            (4, None) => SupervisionEventKind::IntensityExceeded,
+            (5, None) => SupervisionEventKind::Shutdown,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            path: std::str::from_utf8(path).ok()?.to_string(),
+            pid: u32_at(1),
+            time: UNIX_EPOCH + Duration::new(u64_at(5), u32_at(13)),
+        })
+    }
+}
+
+impl Display for SupervisionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            SupervisionEventKind::Started => write!(f, "{} started", self.path),
+            SupervisionEventKind::Exited(exit_status) => {
+                write!(f, "{} exited with {exit_status}", self.path)
+            }
+            SupervisionEventKind::RestartScheduled(delay) => {
+                write!(f, "{} restarting after {delay:?}", self.path)
+            }
+            SupervisionEventKind::GaveUp(exit_status) => {
+                write!(f, "{} given up on after {exit_status}", self.path)
+            }
+            SupervisionEventKind::IntensityExceeded => {
+                write!(f, "{} exceeded its restart intensity", self.path)
+            }
+            SupervisionEventKind::Shutdown => write!(f, "{} shutting down", self.path),
+        }
+    }
+}
+
+/// The stream of events from every supervisor in a tree, which is returned by
+/// [`Supertree::subscribe()`](crate::Supertree::subscribe).
+///
+/// Events are sent up the tree to the process it was started from, and are
+/// buffered until they're received, so the stream can be read from another
+/// thread while the tree runs, or once it has stopped. Events are dropped
+/// rather than holding up the tree when the buffer is full.
+#[derive(Debug)]
+pub struct SupervisionEvents {
+    socket: UnixDatagram,
+}
+
+impl SupervisionEvents {
+    /// Receives the next event, waiting for one to happen.
+    pub fn recv(&self) -> io::Result<SupervisionEvent> {
+        self.recv_event(0)
+    }
+
+    /// Receives the next event, waiting for at most the timeout. Returns
+    /// `None` if no event happened in time.
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<SupervisionEvent>> {
+        // waits with poll rather than the socket's read timeout, which is
+        // shared with the threads receiving from the same stream
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.recv_event(libc::MSG_DONTWAIT) {
+                Ok(event) => return Ok(Some(event)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+            // another thread may take the event poll woke up for, in which
+            // case this one waits for the next until the deadline
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            signal::poll(std::iter::once(self.socket.as_raw_fd()), Some(remaining));
+        }
+    }
+
+    fn recv_event(&self, flags: libc::c_int) -> io::Result<SupervisionEvent> {
+        let mut buf = vec![0; ipc::MAX_FRAME_LEN];
+        let len = loop {
+            let len = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    flags,
+                )
+            };
+            if len >= 0 {
+                break len as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+        SupervisionEvent::decode(&buf[..len])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed event"))
+    }
+}
+
+impl Iterator for SupervisionEvents {
+    type Item = SupervisionEvent;
+
+    /// Waits for the next event, ending the stream if it can't be received.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv().ok()
+    }
+}
+
+/// Where a supervisor sends the events about its children.
+#[derive(Default)]
+pub(crate) enum EventSink {
+    /// Nobody subscribed to the tree's events.
+    #[default]
+    None,
+    /// The events are forwarded to the parent process group, on the way to
+    /// the process the tree was started from.
+    Uplink,
+    /// The events are delivered to the subscribers, in the process the tree
+    /// was started from.
+    Subscribers(Vec<UnixDatagram>),
+}
+
+impl EventSink {
+    /// Adds a subscriber to the events, returning its end of the stream.
+    pub(crate) fn subscribe(&mut self) -> io::Result<SupervisionEvents> {
+        let (subscriber, socket) = UnixDatagram::pair()?;
+        subscriber.set_nonblocking(true)?;
+        match self {
+            Self::Subscribers(subscribers) => subscribers.push(subscriber),
+            _ => *self = Self::Subscribers(vec![subscriber]),
+        }
+        Ok(SupervisionEvents { socket })
+    }
+
+    /// Returns the sink for the processes forked by the supervisor, which
+    /// forward their events if anyone subscribed to them.
+    pub(crate) fn forwarded(&self) -> Self {
+        match self {
+            Self::None => Self::None,
+            _ => Self::Uplink,
+        }
+    }
+}
+
+/// Notifies a supervisor's observer of the lifecycle of its children, and
+/// sends the matching events to its event sink.
+pub(crate) struct Notifier {
+    observer: Arc<dyn SupervisorObserver>,
+    sink: EventSink,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new(Arc::new(()), EventSink::None)
+    }
+}
+
+impl Debug for Notifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Notifier").finish_non_exhaustive()
+    }
+}
+
+impl Notifier {
+    pub(crate) fn new(observer: Arc<dyn SupervisorObserver>, sink: EventSink) -> Self {
+        Self { observer, sink }
+    }
+
+    pub(crate) fn started(&self, path: &str, pid: u32) {
+        self.observer.on_start(path);
+        self.emit(path, pid, SupervisionEventKind::Started);
+    }
+
+    pub(crate) fn exited(&self, path: &str, pid: u32, exit: &ChildExit) {
+        self.observer.on_exit(path, exit);
+        self.emit(path, pid, SupervisionEventKind::Exited(exit.exit_status()));
+    }
+
+    pub(crate) fn restart_scheduled(&self, path: &str, pid: u32, delay: Duration) {
+        self.observer.on_restart(path, delay);
+        self.emit(path, pid, SupervisionEventKind::RestartScheduled(delay));
+    }
+
+    pub(crate) fn gave_up(&self, path: &str, pid: u32, exit: &ChildExit) {
+        self.observer.on_give_up(path, exit);
+        self.emit(path, pid, SupervisionEventKind::GaveUp(exit.exit_status()));
+    }
+
+    /// Reports that the supervisor with the path exceeded its restart
+    /// intensity.
+    pub(crate) fn intensity_exceeded(&self, path: &str) {
+        self.emit(
+            path,
+            std::process::id(),
+            SupervisionEventKind::IntensityExceeded,
+        );
+    }
+
+    /// Reports that the supervisor with the path is shutting down.
+    pub(crate) fn shutdown(&self, path: &str) {
+        self.emit(path, std::process::id(), SupervisionEventKind::Shutdown);
+    }
+
+    fn emit(&self, path: &str, pid: u32, kind: SupervisionEventKind) {
+        if let EventSink::None = self.sink {
+            return;
+        }
+        let event = SupervisionEvent {
+            kind,
+            path: path.to_string(),
+            pid,
+            time: SystemTime::now(),
+        };
+        self.deliver(&event.encode());
+    }
+
+    /// Sends an encoded event on towards the subscribers, including one that
+    /// was forwarded by a child process.
+    pub(crate) fn deliver(&self, event: &[u8]) {
+        match &self.sink {
+            EventSink::None => {}
+            EventSink::Uplink => {
+                let result = ipc::encode_frame(DESTINATION, event).and_then(|frame| {
+                    let uplink = ipc::uplink().ok_or(ipc::IpcError::Disconnected)?;
+                    uplink.send(&frame).map_err(ipc::IpcError::Io)
+                });
+                if let Err(err) = result {
+                    debug!("dropping event err={err}");
+                }
+            }
+            EventSink::Subscribers(subscribers) => {
+                for subscriber in subscribers {
+                    if let Err(err) = subscriber.send(event) {
+                        debug!("dropping event for subscriber err={err}");
+                    }
+                }
+            }
+        }
+    }
+}
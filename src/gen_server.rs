@@ -0,0 +1,275 @@
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::worker::backoff_policy::BackoffPolicy;
+use crate::worker::mailbox::{Mailbox, MailboxSender, SendError};
+use crate::worker::restartable::RestartPolicy;
+use crate::worker::shutdown_policy::ShutdownPolicy;
+use crate::{BoxError, Context, ExitReason, Restartable, Worker};
+
+/// How many messages a server's mailbox holds by default.
+const DEFAULT_MAILBOX_CAPACITY: usize = 1024;
+
+/// A server that handles requests from clients one at a time, like Erlang/OTP's
+/// `gen_server` behaviour.
+///
+/// The server's state is built by [`init()`](Self::init) each time the server
+/// is started, including after it's restarted, and is owned by the loop that
+/// handles its messages. A handler that fails stops the server with its error,
+/// after which it's restarted according to its restart policy. Messages sent
+/// while the server is restarted are kept in its mailbox until they're
+/// handled.
+///
+/// A server is added to a supervisor as a [`GenServerWorker`], and is sent
+/// messages through a [`GenServerClient`].
+pub trait GenServer: Restartable + Debug + Send + Sync + 'static {
+    /// The state built by `init()` and passed to the handlers.
+    type State: Send;
+    /// The requests handled by [`handle_call()`](Self::handle_call).
+    type Call: Send + 'static;
+    /// The replies to requests.
+    type Reply: Send + 'static;
+    /// The messages handled by [`handle_cast()`](Self::handle_cast).
+    type Cast: Send + 'static;
+    /// The messages handled by [`handle_info()`](Self::handle_info).
+    type Info: Send + 'static;
+
+    /// Builds the server's state each time it's started.
+    fn init(&self, ctx: &Context) -> impl Future<Output = Result<Self::State, BoxError>> + Send;
+
+    /// Handles a request, returning the reply sent to the caller.
+    fn handle_call(
+        &self,
+        request: Self::Call,
+        state: &mut Self::State,
+    ) -> impl Future<Output = Result<Self::Reply, BoxError>> + Send;
+
+    /// Handles a message that isn't replied to. By default, the message is
+    /// ignored.
+    fn handle_cast(
+        &self,
+        _message: Self::Cast,
+        _state: &mut Self::State,
+    ) -> impl Future<Output = Result<(), BoxError>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Handles a message sent with [`GenServerClient::send()`], such as a
+    /// notification from a timer or another worker, rather than a request
+    /// from a client. By default, the message is ignored.
+    fn handle_info(
+        &self,
+        _message: Self::Info,
+        _state: &mut Self::State,
+    ) -> impl Future<Output = Result<(), BoxError>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Cleans up once the server stops handling messages, because it was
+    /// asked to stop or a handler failed. Isn't called if a handler panics.
+    fn terminate(
+        &self,
+        _reason: &ExitReason,
+        _state: Self::State,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// A message in a server's mailbox.
+enum Message<S: GenServer> {
+    Call(S::Call, oneshot::Sender<S::Reply>),
+    Cast(S::Cast),
+    Info(S::Info),
+}
+
+/// Runs a [`GenServer`] as a worker in a supervisor.
+pub struct GenServerWorker<S: GenServer> {
+    server: Arc<S>,
+    mailbox: Mailbox<Message<S>>,
+}
+
+impl<S: GenServer> Debug for GenServerWorker<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GenServerWorker")
+            .field("server", &self.server)
+            .field("mailbox", &self.mailbox)
+            .finish()
+    }
+}
+
+impl<S: GenServer> GenServerWorker<S> {
+    /// Creates a worker running the server, with a mailbox that holds up to
+    /// 1024 messages.
+    pub fn new(server: S) -> Self {
+        Self {
+            server: Arc::new(server),
+            mailbox: Mailbox::new(DEFAULT_MAILBOX_CAPACITY),
+        }
+    }
+
+    /// Sets how many messages the server's mailbox holds, after which calls
+    /// and casts fail until the server catches up. Clients created before
+    /// this is called aren't connected to the new mailbox.
+    pub fn with_mailbox_capacity(mut self, capacity: usize) -> Self {
+        self.mailbox = Mailbox::new(capacity);
+        self
+    }
+
+    /// Returns a client for sending messages to the server, which can be
+    /// cloned and used by the other workers in the same supervisor.
+    pub fn client(&self) -> GenServerClient<S> {
+        GenServerClient {
+            sender: self.mailbox.sender(),
+        }
+    }
+}
+
+impl<S: GenServer> Worker for GenServerWorker<S> {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send + 'static>> {
+        let server = self.server.clone();
+        let mailbox = self.mailbox.clone();
+        Box::pin(async move {
+            let mut state = server.init(&ctx).await?;
+            let shutdown = ctx.shutdown();
+            let result = loop {
+                let message = tokio::select! {
+                    message = mailbox.recv() => message,
+                    _ = shutdown.requested() => break Ok(()),
+                };
+                let result = match message {
+                    Some(Message::Call(request, reply)) => server
+                        .handle_call(request, &mut state)
+                        .await
+                        .map(|response| {
+                            // the caller may have given up waiting
+                            let _ = reply.send(response);
+                        }),
+                    Some(Message::Cast(message)) => server.handle_cast(message, &mut state).await,
+                    Some(Message::Info(message)) => server.handle_info(message, &mut state).await,
+                    None => {
+                        // without clients, there's nothing left to handle
+                        shutdown.requested().await;
+                        break Ok(());
+                    }
+                };
+                if result.is_err() {
+                    break result;
+                }
+            };
+            let reason = ExitReason::from_result(result, shutdown.is_requested());
+            server.terminate(&reason, state).await;
+            match reason {
+                ExitReason::Error(err) => Err(err),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+impl<S: GenServer> Restartable for GenServerWorker<S> {
+    fn restart_policy(&self) -> RestartPolicy {
+        self.server.restart_policy()
+    }
+
+    fn backoff_policy(&self) -> BackoffPolicy {
+        self.server.backoff_policy()
+    }
+
+    fn shutdown_policy(&self) -> ShutdownPolicy {
+        self.server.shutdown_policy()
+    }
+}
+
+/// A handle for sending messages to a [`GenServer`], which can be cloned and
+/// shared between the workers of a supervisor.
+pub struct GenServerClient<S: GenServer> {
+    sender: MailboxSender<Message<S>>,
+}
+
+impl<S: GenServer> Clone for GenServerClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S: GenServer> Debug for GenServerClient<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GenServerClient").finish_non_exhaustive()
+    }
+}
+
+impl<S: GenServer> GenServerClient<S> {
+    fn send_message(&self, message: Message<S>) -> Result<(), GenServerError> {
+        self.sender.send(message).map_err(|err| match err {
+            SendError::Full(_) => GenServerError::Full,
+            SendError::Closed(_) => GenServerError::Closed,
+        })
+    }
+
+    /// Sends the request to the server and waits for its reply, for at most
+    /// the timeout, which includes the time the request waits in the mailbox
+    /// while the server is busy or restarting.
+    pub async fn call(
+        &self,
+        request: S::Call,
+        timeout: Duration,
+    ) -> Result<S::Reply, GenServerError> {
+        let (reply, response) = oneshot::channel();
+        self.send_message(Message::Call(request, reply))?;
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(GenServerError::NoReply),
+            Err(_) => Err(GenServerError::Timeout),
+        }
+    }
+
+    /// Sends the message to the server without waiting for it to be handled.
+    pub fn cast(&self, message: S::Cast) -> Result<(), GenServerError> {
+        self.send_message(Message::Cast(message))
+    }
+
+    /// Sends the message to the server's
+    /// [`handle_info()`](GenServer::handle_info) without waiting for it to
+    /// be handled.
+    pub fn send(&self, message: S::Info) -> Result<(), GenServerError> {
+        self.send_message(Message::Info(message))
+    }
+}
+
+/// Represents an error returned when sending a message to a [`GenServer`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GenServerError {
+    /// The server's mailbox is full.
+    Full,
+    /// The server's worker has been dropped, because the tree has stopped.
+    Closed,
+    /// The server didn't reply within the timeout.
+    Timeout,
+    /// The server stopped before replying, because it failed or was asked to
+    /// stop.
+    NoReply,
+}
+
+impl Display for GenServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "server's mailbox is full"),
+            Self::Closed => write!(f, "server is closed"),
+            Self::Timeout => write!(f, "server didn't reply in time"),
+            Self::NoReply => write!(f, "server stopped before replying"),
+        }
+    }
+}
+
+impl std::error::Error for GenServerError {}
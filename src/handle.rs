@@ -0,0 +1,536 @@
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use log::debug;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::spawn;
+
+/// Counts the control sockets bound by this process, so that each supervisor
+/// gets its own address.
+static NEXT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// How long the control socket waits before accepting connections again after
+/// failing to accept one, such as when the process has run out of fds.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long a request waits for the supervisor by default, before failing
+/// with [`HandleError::Timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Requests from handles, along with where to send the response, as received
+/// by the watcher serving a supervisor's control socket.
+pub(crate) type Requests = mpsc::UnboundedReceiver<(Request, oneshot::Sender<Response>)>;
+
+/// A handle for managing a supervisor's children at runtime, which can be
+/// cloned and used from any thread or process in the tree.
+///
+/// Requests are sent to the supervisor over a unix socket, and block until the
+/// supervisor has handled them. The socket is bound when the handle is
+/// created, so requests made before the tree is started, or while the
+/// supervisor is being restarted, wait until the supervisor is running, for
+/// as long as the handle's [timeout](Self::with_timeout) allows.
+///
+/// Since requests block the calling thread, a worker making them from its
+/// async code should do so on a blocking thread, such as with
+/// [`tokio::task::spawn_blocking()`], rather than on one of the runtime's
+/// threads, which its watcher may need to handle the request.
+///
+/// Workers are started at runtime from templates added to the supervisor with
+/// [`add_template()`](crate::Supervisor::add_template). Children are
+/// identified by their ID, which for workers and supervisors added when the
+/// tree is built is their name. Child supervisors can be terminated,
+/// restarted, deleted and listed like workers, but only workers can be
+/// started from templates.
+#[derive(Debug, Clone)]
+pub struct SupervisorHandle {
+    name: String,
+    address: SocketAddr,
+    timeout: Option<Duration>,
+}
+
+/// Represents a child of a supervisor, as listed by
+/// [`SupervisorHandle::which_children()`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ChildInfo {
+    id: String,
+    running: bool,
+}
+
+impl ChildInfo {
+    /// Returns the ID of the child.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns true if the child is running, rather than having been
+    /// terminated or given up on.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+/// Represents the number of children of a supervisor, as returned by
+/// [`SupervisorHandle::count_children()`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChildCount {
+    specs: usize,
+    active: usize,
+}
+
+impl ChildCount {
+    /// Returns the number of children, whether or not they're running.
+    pub fn specs(&self) -> usize {
+        self.specs
+    }
+
+    /// Returns the number of running children.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+}
+
+/// Represents an error returned by a [`SupervisorHandle`] request.
+#[derive(Debug)]
+pub enum HandleError {
+    /// The supervisor couldn't be reached, for example because the tree has
+    /// stopped.
+    Io(io::Error),
+    /// The template name or child ID contains a tab or newline, or the child
+    /// ID is empty, contains a `/` or is the reserved name `watcher`.
+    InvalidName(String),
+    /// There's no template with the name.
+    UnknownTemplate(String),
+    /// There's already a child with the ID.
+    AlreadyExists(String),
+    /// There's no child with the ID.
+    NotFound(String),
+    /// The child with the ID is running, and must be terminated first.
+    Running(String),
+    /// The supervisor already has as many children as it may have.
+    MaxChildren,
+    /// The supervisor is shutting down.
+    ShuttingDown,
+    /// The supervisor didn't respond within the handle's timeout.
+    Timeout,
+    /// The supervisor sent a response that couldn't be decoded.
+    Protocol(String),
+}
+
+impl Display for HandleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't reach supervisor: {err}"),
+            Self::InvalidName(name) => write!(f, "invalid name {name:?}"),
+            Self::UnknownTemplate(name) => write!(f, "unknown template {name:?}"),
+            Self::AlreadyExists(id) => write!(f, "child {id:?} already exists"),
+            Self::NotFound(id) => write!(f, "child {id:?} not found"),
+            Self::Running(id) => write!(f, "child {id:?} is running"),
+            Self::MaxChildren => write!(f, "supervisor has its maximum number of children"),
+            Self::ShuttingDown => write!(f, "supervisor is shutting down"),
+            Self::Timeout => write!(f, "supervisor didn't respond in time"),
+            Self::Protocol(response) => write!(f, "invalid response {response:?}"),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HandleError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A request sent by a handle, one per connection, as a line of tab
+/// separated fields.
+#[derive(Debug)]
+pub(crate) enum Request {
+    StartChild { template: String, id: String },
+    TerminateChild(String),
+    RestartChild(String),
+    DeleteChild(String),
+    WhichChildren,
+    CountChildren,
+    Shutdown,
+}
+
+impl Request {
+    fn encode(&self) -> Result<String, HandleError> {
+        let fields: Vec<&str> = match self {
+            Self::StartChild { template, id } => vec!["start", template, id],
+            Self::TerminateChild(id) => vec!["terminate", id],
+            Self::RestartChild(id) => vec!["restart", id],
+            Self::DeleteChild(id) => vec!["delete", id],
+            Self::WhichChildren => vec!["which"],
+            Self::CountChildren => vec!["count"],
+            Self::Shutdown => vec!["shutdown"],
+        };
+        if let Some(field) = fields.iter().find(|field| field.contains(['\t', '\n'])) {
+            return Err(HandleError::InvalidName(field.to_string()));
+        }
+        Ok(fields.join("\t"))
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[..] {
+            ["start", template, id] => Some(Self::StartChild {
+                template: template.to_string(),
+                id: id.to_string(),
+            }),
+            ["terminate", id] => Some(Self::TerminateChild(id.to_string())),
+            ["restart", id] => Some(Self::RestartChild(id.to_string())),
+            ["delete", id] => Some(Self::DeleteChild(id.to_string())),
+            ["which"] => Some(Self::WhichChildren),
+            ["count"] => Some(Self::CountChildren),
+            ["shutdown"] => Some(Self::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+/// The response to a request, sent back on the request's connection.
+#[derive(Debug)]
+pub(crate) enum Response {
+    Ok,
+    Children(Vec<ChildInfo>),
+    Count(ChildCount),
+    Err(HandleError),
+}
+
+impl Response {
+    pub(crate) fn children<'a>(children: impl Iterator<Item = (&'a str, bool)>) -> Self {
+        Self::Children(
+            children
+                .map(|(id, running)| ChildInfo {
+                    id: id.to_string(),
+                    running,
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn count(specs: usize, active: usize) -> Self {
+        Self::Count(ChildCount { specs, active })
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Self::Ok => "ok".to_string(),
+            Self::Children(children) => {
+                std::iter::once("children".to_string())
+                    .chain(children.iter().map(|child| {
+                        format!("{}\t{}", child.id, if child.running { 1 } else { 0 })
+                    }))
+                    .collect::<Vec<_>>()
+                    .join("\t")
+            }
+            Self::Count(count) => format!("count\t{}\t{}", count.specs, count.active),
+            Self::Err(err) => match err {
+                HandleError::InvalidName(name) => format!("err\tinvalid_name\t{name}"),
+                HandleError::UnknownTemplate(name) => format!("err\tunknown_template\t{name}"),
+                HandleError::AlreadyExists(id) => format!("err\talready_exists\t{id}"),
+                HandleError::NotFound(id) => format!("err\tnot_found\t{id}"),
+                HandleError::Running(id) => format!("err\trunning\t{id}"),
+                HandleError::MaxChildren => "err\tmax_children".to_string(),
+                HandleError::ShuttingDown => "err\tshutting_down".to_string(),
+                err => format!(
+                    "err\tprotocol\t{}",
+                    err.to_string().replace(['\t', '\n'], " ")
+                ),
+            },
+        }
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[..] {
+            ["ok"] => Some(Self::Ok),
+            ["children", ref children @ ..] if children.len() % 2 == 0 => Some(Self::Children(
+                children
+                    .chunks(2)
+                    .map(|child| ChildInfo {
+                        id: child[0].to_string(),
+                        running: child[1] == "1",
+                    })
+                    .collect(),
+            )),
+            ["count", specs, active] => {
+                Some(Self::count(specs.parse().ok()?, active.parse().ok()?))
+            }
+            ["err", "invalid_name", name] => {
+                Some(Self::Err(HandleError::InvalidName(name.to_string())))
+            }
+            ["err", "unknown_template", name] => {
+                Some(Self::Err(HandleError::UnknownTemplate(name.to_string())))
+            }
+            ["err", "already_exists", id] => {
+                Some(Self::Err(HandleError::AlreadyExists(id.to_string())))
+            }
+            ["err", "not_found", id] => Some(Self::Err(HandleError::NotFound(id.to_string()))),
+            ["err", "running", id] => Some(Self::Err(HandleError::Running(id.to_string()))),
+            ["err", "max_children"] => Some(Self::Err(HandleError::MaxChildren)),
+            ["err", "shutting_down"] => Some(Self::Err(HandleError::ShuttingDown)),
+            ["err", "protocol", message] => {
+                Some(Self::Err(HandleError::Protocol(message.to_string())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl SupervisorHandle {
+    /// Binds a new control socket, returning the listener along with a handle
+    /// for it.
+    ///
+    /// The socket is named after the process the tree was started from and
+    /// the tree's random key, so that a process re-executed to run a node of
+    /// the tree names it the same way, and takes over the socket it inherited
+    /// rather than binding it, while other users can't guess the name. A
+    /// socket the process didn't inherit is served by another process, so
+    /// there's no listener for it.
+    ///
+    /// Linux sockets are in the abstract namespace, and sockets on other
+    /// platforms are in a directory only the tree's user can access.
+    pub(crate) fn bind() -> io::Result<(Option<UnixListener>, Self)> {
+        let index = NEXT_ADDRESS.fetch_add(1, Ordering::SeqCst);
+        let name = format!(
+            "supertrees-{}-{}-{index}",
+            spawn::root_pid(),
+            spawn::tree_key(),
+        );
+        #[cfg(target_os = "linux")]
+        let address = {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(&name)?
+        };
+        #[cfg(not(target_os = "linux"))]
+        let path = private_dir()?.join(format!("{index}.sock"));
+        #[cfg(not(target_os = "linux"))]
+        let address = SocketAddr::from_pathname(&path)?;
+        let listener = match spawn::inherited_listener(&name) {
+            Some(listener) => Some(listener),
+            None if spawn::is_node() => None,
+            None => {
+                #[cfg(not(target_os = "linux"))]
+                let _ = std::fs::remove_file(&path);
+                Some(UnixListener::bind_addr(&address)?)
+            }
+        };
+        Ok((
+            listener,
+            Self {
+                name,
+                address,
+                timeout: Some(DEFAULT_TIMEOUT),
+            },
+        ))
+    }
+
+    /// Returns the name of the handle's control socket.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets how long requests wait for the supervisor to respond, which
+    /// includes waiting for a terminated child to stop. Defaults to 30
+    /// seconds, and `None` waits for as long as it takes.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn request(&self, request: Request) -> Result<Response, HandleError> {
+        let line = request.encode()?;
+        let mut stream = UnixStream::connect_addr(&self.address)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        let timed_out = |err: io::Error| match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => HandleError::Timeout,
+            _ => HandleError::Io(err),
+        };
+        writeln!(stream, "{line}").map_err(timed_out)?;
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .map_err(timed_out)?;
+        let response = response.trim_end_matches('\n');
+        match Response::decode(response) {
+            Some(Response::Err(err)) => Err(err),
+            Some(response) => Ok(response),
+            None => Err(HandleError::Protocol(response.to_string())),
+        }
+    }
+
+    fn request_ok(&self, request: Request) -> Result<(), HandleError> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            response => Err(HandleError::Protocol(response.encode())),
+        }
+    }
+
+    /// Starts a new worker with the ID from the named template.
+    pub fn start_child(&self, template: &str, id: &str) -> Result<(), HandleError> {
+        self.request_ok(Request::StartChild {
+            template: template.to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Stops the child according to its shutdown policy, returning once it
+    /// has stopped. The child isn't restarted until
+    /// [`restart_child()`](Self::restart_child) is called, even when the
+    /// supervisor's strategy restarts its siblings.
+    pub fn terminate_child(&self, id: &str) -> Result<(), HandleError> {
+        self.request_ok(Request::TerminateChild(id.to_string()))
+    }
+
+    /// Starts a child that was terminated or given up on.
+    pub fn restart_child(&self, id: &str) -> Result<(), HandleError> {
+        self.request_ok(Request::RestartChild(id.to_string()))
+    }
+
+    /// Removes a child that was terminated or given up on, so that it can't
+    /// be restarted.
+    pub fn delete_child(&self, id: &str) -> Result<(), HandleError> {
+        self.request_ok(Request::DeleteChild(id.to_string()))
+    }
+
+    /// Lists the supervisor's children in the order they were added, followed
+    /// by the workers started from templates in their start order.
+    pub fn which_children(&self) -> Result<Vec<ChildInfo>, HandleError> {
+        match self.request(Request::WhichChildren)? {
+            Response::Children(children) => Ok(children),
+            response => Err(HandleError::Protocol(response.encode())),
+        }
+    }
+
+    /// Counts the supervisor's children.
+    pub fn count_children(&self) -> Result<ChildCount, HandleError> {
+        match self.request(Request::CountChildren)? {
+            Response::Count(count) => Ok(count),
+            response => Err(HandleError::Protocol(response.encode())),
+        }
+    }
+
+    /// Stops the supervisor's workers, after which the supervisor stops once
+    /// its child supervisors have.
+    pub fn shutdown(&self) -> Result<(), HandleError> {
+        match self.request(Request::Shutdown) {
+            Ok(Response::Ok) => Ok(()),
+            // a supervisor without children left to stop can be gone before
+            // its reply is written, closing the connection without one
+            Err(HandleError::Protocol(response)) if response.is_empty() => Ok(()),
+            Ok(response) => Err(HandleError::Protocol(response.encode())),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Returns the directory the tree's control sockets are bound in, on
+/// platforms without abstract sockets, creating it if it doesn't exist. Fails
+/// if the directory exists but isn't private to the tree's user, since anyone
+/// who can write to it could replace the sockets.
+#[cfg(not(target_os = "linux"))]
+fn private_dir() -> io::Result<std::path::PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    let dir = std::env::temp_dir().join(format!(
+        "supertrees-{}-{}",
+        spawn::root_pid(),
+        spawn::tree_key()
+    ));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+        _ => {}
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::geteuid() }
+        || metadata.permissions().mode() & 0o077 != 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't private to the tree's user", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+/// Serves requests on the control socket from a task on the current runtime,
+/// returning the receiver they're forwarded to. Connections from processes
+/// running as another user than the tree are closed without being served.
+pub(crate) fn serve(listener: UnixListener) -> io::Result<Requests> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::UnixListener::from_std(listener)?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let uid = unsafe { libc::geteuid() };
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    // accepting keeps failing while the process is out of
+                    // fds, so the loop waits for some to be closed rather
+                    // than spinning
+                    debug!("failed to accept control connection err={err}");
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            match stream.peer_cred() {
+                Ok(cred) if cred.uid() == uid => {}
+                Ok(cred) => {
+                    debug!("rejecting control connection from uid={}", cred.uid());
+                    continue;
+                }
+                Err(err) => {
+                    debug!("failed to check control connection's peer err={err}");
+                    continue;
+                }
+            }
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_connection(stream, tx).await {
+                    debug!("control connection failed err={err}");
+                }
+            });
+        }
+    });
+    Ok(rx)
+}
+
+async fn serve_connection(
+    stream: tokio::net::UnixStream,
+    tx: mpsc::UnboundedSender<(Request, oneshot::Sender<Response>)>,
+) -> io::Result<()> {
+    let mut stream = tokio::io::BufReader::new(stream);
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let response = match Request::decode(line.trim_end_matches('\n')) {
+        Some(request) => {
+            let (reply, response) = oneshot::channel();
+            let _ = tx.send((request, reply));
+            // the watcher drops the request when it's stopping
+            response
+                .await
+                .unwrap_or(Response::Err(HandleError::ShuttingDown))
+        }
+        None => Response::Err(HandleError::Protocol(line)),
+    };
+    let mut response = response.encode();
+    response.push('\n');
+    stream.get_mut().write_all(response.as_bytes()).await
+}
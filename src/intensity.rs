@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Represents the restart intensity of a supervisor, which is the maximum
+/// number of restarts of its children allowed within a period of time. When
+/// the intensity is exceeded, the supervisor stops all of its children and
+/// exits, leaving the decision to restart it up to its own supervisor.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartIntensity {
+    max_restarts: usize,
+    period: Duration,
+}
+
+impl RestartIntensity {
+    /// Creates a new `RestartIntensity` with the specified parameters.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_restarts` - The maximum number of restarts within the period.
+    /// * `period` - The period over which restarts are counted.
+    pub fn new(max_restarts: usize, period: Duration) -> Self {
+        debug_assert!(period > Duration::from_millis(0));
+        Self {
+            max_restarts,
+            period,
+        }
+    }
+
+    /// Returns the maximum number of restarts within the period.
+    pub fn max_restarts(&self) -> usize {
+        self.max_restarts
+    }
+
+    /// Returns the period over which restarts are counted.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+/// Keeps track of the restarts of a supervisor's children, checking them
+/// against its restart intensity.
+#[derive(Debug)]
+pub(crate) struct RestartBudget {
+    intensity: Option<RestartIntensity>,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    pub fn new(intensity: Option<RestartIntensity>) -> Self {
+        Self {
+            intensity,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Records a restart, returning false if the restart exceeds the
+    /// intensity.
+    pub fn record(&mut self) -> bool {
+        let Some(intensity) = self.intensity else {
+            return true;
+        };
+        let now = Instant::now();
+        while let Some(ts) = self.restarts.front() {
+            if now - *ts > intensity.period() {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.restarts.push_back(now);
+        self.restarts.len() <= intensity.max_restarts()
+    }
+}
@@ -0,0 +1,68 @@
+use crate::BoxError;
+
+/// Turns messages of type `M` into bytes and back, for sending them between
+/// the processes of the tree with an [`IpcSender`](crate::IpcSender).
+///
+/// Codecs for [serde](https://serde.rs) types are provided behind the `json`
+/// and `bincode` features, and other formats can be used by implementing this
+/// trait.
+pub trait Codec<M>: Send + Sync + 'static {
+    /// Encodes the message.
+    fn encode(&self, message: &M) -> Result<Vec<u8>, BoxError>;
+
+    /// Decodes a message encoded by [`encode()`](Self::encode).
+    fn decode(&self, bytes: &[u8]) -> Result<M, BoxError>;
+}
+
+/// A codec that sends raw bytes as they are.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytesCodec;
+
+impl Codec<Vec<u8>> for BytesCodec {
+    fn encode(&self, message: &Vec<u8>) -> Result<Vec<u8>, BoxError> {
+        Ok(message.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, BoxError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// A codec that encodes messages as JSON.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<M> Codec<M> for JsonCodec
+where
+    M: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(&self, message: &M) -> Result<Vec<u8>, BoxError> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<M, BoxError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// A codec that encodes messages with bincode, which is more compact than
+/// JSON.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<M> Codec<M> for BincodeCodec
+where
+    M: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(&self, message: &M) -> Result<Vec<u8>, BoxError> {
+        Ok(bincode::serialize(message)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<M, BoxError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
@@ -0,0 +1,384 @@
+pub mod codec;
+
+use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use self::codec::Codec;
+use crate::BoxError;
+use crate::worker::mailbox::Mailbox;
+
+/// The largest frame that can be sent between processes, including the
+/// destination it's addressed to.
+pub(crate) const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// The link from the current process to the process group that forked it,
+/// which isn't set in the process the tree was started from.
+static UPLINK: Mutex<Option<Arc<UnixDatagram>>> = Mutex::new(None);
+
+thread_local! {
+    /// The link from the current thread to the process group that started
+    /// it, in a child run in a thread rather than in a process of its own,
+    /// and in the threads of the runtime running its workers.
+    static THREAD_UPLINK: RefCell<Option<Arc<UnixDatagram>>> = const { RefCell::new(None) };
+}
+
+tokio::task_local! {
+    /// The link from a worker's task to the process group that started its
+    /// watcher, which follows the task onto whichever thread of the runtime
+    /// it's polled on, including those of a runtime the watcher shares with
+    /// the rest of the process.
+    static TASK_UPLINK: Option<Arc<UnixDatagram>>;
+}
+
+/// The destination of the frame a process group sends a child running in a
+/// thread to ask it to stop, in place of SIGTERM, which can't be sent to a
+/// thread. It can't be the path of a worker, since names can't contain tabs.
+pub(crate) const SHUTDOWN: &str = "\tshutdown";
+
+/// The destination of the frames a watcher asks its process group to restart
+/// one of its workers with, and of those the group answers with, once it has
+/// decided which of its children to restart along with the worker.
+pub(crate) const RESTART: &str = "\trestart";
+
+/// The destination of the frames a watcher asks its process group to stop one
+/// of the supervisor's child supervisors with, for the supervisor's handle,
+/// carrying the child's name. The child isn't restarted until it's started
+/// again.
+pub(crate) const TERMINATE: &str = "\tterminate";
+
+/// The destination of the frames a watcher asks its process group to start
+/// one of the supervisor's child supervisors that has stopped with, carrying
+/// the child's name.
+pub(crate) const START: &str = "\tstart";
+
+/// The destination of the frames a process group tells its watcher that one
+/// of the supervisor's child supervisors has started or stopped with.
+pub(crate) const CHILD: &str = "\tchild";
+
+/// Whether one of a supervisor's child supervisors is running, as the process
+/// group tells the watcher whenever it changes, so that the supervisor's
+/// handle lists the child along with the workers. A child backing off before
+/// it's restarted counts as running, like a worker does.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ChildState {
+    pub name: String,
+    pub running: bool,
+}
+
+impl ChildState {
+    /// Encodes the state as 1 for a running child or 0 for a stopped one,
+    /// followed by the child's name.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + self.name.len());
+        buf.push(self.running as u8);
+        buf.extend_from_slice(self.name.as_bytes());
+        buf
+    }
+
+    /// Decodes a state, if it's well formed.
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (running, name) = buf.split_first()?;
+        Some(Self {
+            name: std::str::from_utf8(name).ok()?.to_string(),
+            running: *running != 0,
+        })
+    }
+
+    /// Sends the state on the link.
+    pub fn send(&self, link: &UnixDatagram) -> Result<(), IpcError> {
+        let frame = encode_frame(CHILD, &self.encode())?;
+        link.send(&frame).map(|_| ()).map_err(IpcError::Io)
+    }
+}
+
+/// A restart of a supervisor's children, which spans its workers and its
+/// child supervisors alike.
+///
+/// A watcher asks for the worker with the name to be restarted, giving the
+/// worker's position among the supervisor's children as the range. The
+/// process group answers with the positions of the children its strategy
+/// restarts along with it, for the watcher to restart those that are its
+/// workers. Workers started from templates come after the children the
+/// supervisor was built with, in the order they were started.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Restart {
+    /// The name of the worker that stopped, which is empty for a restart
+    /// caused by one of the group's other children.
+    pub name: String,
+    pub delay: Duration,
+    pub range: Range<usize>,
+}
+
+impl Restart {
+    /// Encodes the restart as its delay in milliseconds, the start and end of
+    /// its range and then the worker's name.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(24 + self.name.len());
+        buf.extend_from_slice(&(self.delay.as_millis() as u64).to_be_bytes());
+        buf.extend_from_slice(&(self.range.start as u64).to_be_bytes());
+        buf.extend_from_slice(&(self.range.end as u64).to_be_bytes());
+        buf.extend_from_slice(self.name.as_bytes());
+        buf
+    }
+
+    /// Decodes a restart, if it's well formed.
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 24 {
+            return None;
+        }
+        let (header, name) = buf.split_at(24);
+        let field = |index: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[index * 8..index * 8 + 8]);
+            u64::from_be_bytes(bytes)
+        };
+        Some(Self {
+            name: std::str::from_utf8(name).ok()?.to_string(),
+            delay: Duration::from_millis(field(0)),
+            range: field(1) as usize..field(2) as usize,
+        })
+    }
+
+    /// Sends the restart on the link.
+    pub fn send(&self, link: &UnixDatagram) -> Result<(), IpcError> {
+        let frame = encode_frame(RESTART, &self.encode())?;
+        link.send(&frame).map(|_| ()).map_err(IpcError::Io)
+    }
+}
+
+/// Creates the link between a process group and a child it's about to fork,
+/// as a pair of connected datagram sockets, so that each frame is sent and
+/// received whole. Both ends are non-blocking, so that a process that isn't
+/// reading its frames can't hold up the one sending them.
+pub(crate) fn link() -> io::Result<(UnixDatagram, UnixDatagram)> {
+    let (parent, child) = UnixDatagram::pair()?;
+    parent.set_nonblocking(true)?;
+    child.set_nonblocking(true)?;
+    Ok((parent, child))
+}
+
+/// Sets the link to the parent process group, in a newly forked child.
+pub(crate) fn set_uplink(uplink: UnixDatagram) {
+    *UPLINK.lock().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(uplink));
+}
+
+/// Sets the link to the parent process group for the current thread, which
+/// takes precedence over the process's own link.
+pub(crate) fn set_thread_uplink(uplink: Arc<UnixDatagram>) {
+    THREAD_UPLINK.with(|cell| *cell.borrow_mut() = Some(uplink));
+}
+
+/// Runs the future with the current link to the parent process group, which
+/// takes precedence over the link of the thread it's polled on.
+pub(crate) fn with_uplink<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    TASK_UPLINK.scope(uplink(), future)
+}
+
+/// Returns the link to the parent process group, if the current task, thread
+/// or process was started by one.
+pub(crate) fn uplink() -> Option<Arc<UnixDatagram>> {
+    TASK_UPLINK
+        .try_with(Clone::clone)
+        .ok()
+        .flatten()
+        .or_else(|| THREAD_UPLINK.with(|cell| cell.borrow().clone()))
+        .or_else(|| UPLINK.lock().unwrap_or_else(|err| err.into_inner()).clone())
+}
+
+/// Returns true if the path is the ancestor's path, or the path of one of its
+/// descendants.
+pub(crate) fn is_within(path: &str, ancestor: &str) -> bool {
+    path.strip_prefix(ancestor)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Frames the payload along with the path of the worker it's addressed to, as
+/// the length of the path, the path and then the payload.
+pub(crate) fn encode_frame(destination: &str, payload: &[u8]) -> Result<Vec<u8>, IpcError> {
+    let len = 2 + destination.len() + payload.len();
+    if len > MAX_FRAME_LEN {
+        return Err(IpcError::TooLarge(len));
+    }
+    let mut frame = Vec::with_capacity(len);
+    frame.extend_from_slice(&(destination.len() as u16).to_be_bytes());
+    frame.extend_from_slice(destination.as_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Splits a frame into the path of the worker it's addressed to and its
+/// payload, if it's well formed.
+pub(crate) fn decode_frame(frame: &[u8]) -> Option<(&str, &[u8])> {
+    if frame.len() < 2 {
+        return None;
+    }
+    let (len, rest) = frame.split_at(2);
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (destination, payload) = rest.split_at(len);
+    Some((std::str::from_utf8(destination).ok()?, payload))
+}
+
+/// A handle for sending messages of type `M` to the worker with a path, such
+/// as `root/db/pool`, from a worker anywhere in the tree.
+///
+/// Messages are encoded with the codec and sent up the tree to the closest
+/// supervisor the two workers share, then down to the destination. They're
+/// buffered in the destination worker's inbox while it's restarted, and
+/// received with an [`IpcReceiver`], which is returned by
+/// [`Context::receiver()`](crate::Context::receiver). Messages addressed to a
+/// worker that doesn't exist, or that arrive while the supervisor running it
+/// is restarted, are dropped.
+pub struct IpcSender<M, C> {
+    destination: String,
+    codec: C,
+    message: PhantomData<fn(M)>,
+}
+
+impl<M, C: Codec<M>> IpcSender<M, C> {
+    /// Creates a sender for the worker with the path.
+    pub fn new(destination: &str, codec: C) -> Self {
+        Self {
+            destination: destination.to_string(),
+            codec,
+            message: PhantomData,
+        }
+    }
+
+    /// Returns the path of the worker messages are sent to.
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Sends the message without waiting. Fails if the destination isn't the
+    /// path of a worker, if the message can't be encoded or is too large, or
+    /// if the link to the rest of the tree is full or missing, which it is
+    /// outside of the tree's workers.
+    pub fn send(&self, message: &M) -> Result<(), IpcError> {
+        // destinations starting with a tab address the tree's own processes,
+        // which trust the frames on their links not to come from workers
+        if self.destination.starts_with('\t') {
+            return Err(IpcError::InvalidDestination(self.destination.clone()));
+        }
+        let payload = self.codec.encode(message).map_err(IpcError::Encode)?;
+        let frame = encode_frame(&self.destination, &payload)?;
+        let uplink = uplink().ok_or(IpcError::Disconnected)?;
+        match uplink.send(&frame) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Err(IpcError::Full),
+            Err(err) => Err(IpcError::Io(err)),
+        }
+    }
+}
+
+impl<M, C: Clone> Clone for IpcSender<M, C> {
+    fn clone(&self) -> Self {
+        Self {
+            destination: self.destination.clone(),
+            codec: self.codec.clone(),
+            message: PhantomData,
+        }
+    }
+}
+
+impl<M, C> Debug for IpcSender<M, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IpcSender")
+            .field("destination", &self.destination)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receives the messages of type `M` sent to a worker by
+/// [`IpcSender`]s elsewhere in the tree.
+pub struct IpcReceiver<M, C> {
+    inbox: Mailbox<Vec<u8>>,
+    codec: C,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M, C: Codec<M>> IpcReceiver<M, C> {
+    pub(crate) fn new(inbox: Mailbox<Vec<u8>>, codec: C) -> Self {
+        Self {
+            inbox,
+            codec,
+            message: PhantomData,
+        }
+    }
+
+    /// Receives the next message, waiting for one to be sent. Fails if the
+    /// message can't be decoded, in which case the next one can still be
+    /// received.
+    pub async fn recv(&self) -> Result<M, IpcError> {
+        let payload = self.inbox.recv().await.ok_or(IpcError::Disconnected)?;
+        self.codec.decode(&payload).map_err(IpcError::Decode)
+    }
+}
+
+impl<M, C> Debug for IpcReceiver<M, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IpcReceiver")
+            .field("inbox", &self.inbox)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Represents an error returned when sending or receiving a message between
+/// processes.
+#[derive(Debug)]
+pub enum IpcError {
+    /// The link to the rest of the tree failed.
+    Io(io::Error),
+    /// The message couldn't be encoded.
+    Encode(BoxError),
+    /// The message couldn't be decoded.
+    Decode(BoxError),
+    /// The framed message is larger than the largest frame that can be sent.
+    TooLarge(usize),
+    /// The destination isn't the path of a worker, but is reserved for the
+    /// tree's own frames.
+    InvalidDestination(String),
+    /// The link to the rest of the tree is full, because messages are being
+    /// sent faster than they're routed.
+    Full,
+    /// The current process isn't linked to the rest of the tree, because it's
+    /// the process the tree was started from.
+    Disconnected,
+}
+
+impl Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "link failed: {err}"),
+            Self::Encode(err) => write!(f, "couldn't encode message: {err}"),
+            Self::Decode(err) => write!(f, "couldn't decode message: {err}"),
+            Self::TooLarge(len) => {
+                write!(f, "frame of {len} bytes exceeds {MAX_FRAME_LEN} bytes")
+            }
+            Self::InvalidDestination(destination) => {
+                write!(f, "invalid destination {destination:?}")
+            }
+            Self::Full => write!(f, "link is full"),
+            Self::Disconnected => write!(f, "not linked to the tree"),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Encode(err) | Self::Decode(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
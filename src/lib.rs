@@ -14,9 +14,11 @@
 //! be used for production services, unless you are very excited about the idea
 //! and would be willing to contribute to the development of the crate. Notably,
 //! this crate lacks a lot of the features that are present in Erlang/OTP, such
-//! as monitoring, tracing, and distributed messaging, although Tokio provides a
-//! tracing and metrics system that could be used in conjunction with this crate
-//! (it has just not been tested yet).
+//! as tracing the messages workers send each other, and messaging between nodes
+//! on different hosts (its IPC only reaches the processes of the same tree).
+//! Supervision itself can be watched with a [`SupervisorObserver`] or the
+//! tree's [`SupervisionEvents`], and workers can monitor or link to their
+//! siblings.
 //!
 //! For detailed examples, refer to the integration tests in the
 //! [`tests`](https://github.com/brndnmtthws/supertrees/tree/main/tests)
@@ -28,25 +30,86 @@
 //! - **Workers**: Add workers to the supervision tree
 //! - **Async workers**: Workers are async and can use async/await syntax
 //! - **Process isolation**: The tree is constructed by forking processes,
-//!   providing additional isolation (_IPC not currently implemented_)
+//!   providing additional isolation
+//! - **IPC**: Workers in different supervisors, and so in different processes,
+//!   can send each other typed messages with an [`IpcSender`], encoded by a
+//!   [`Codec`]. Codecs for serde types are provided by the `json` and `bincode`
+//!   features
+//! - **Fork safety**: Starting a tree from a process that already runs other
+//!   threads, whose locks its forked children could deadlock on, is refused by
+//!   default
+//! - **Re-exec backend**: Start each of the tree's processes by executing the
+//!   program again, rather than by forking, with [`Backend::ReExec`]
+//! - **Thread backend**: Keep the supervision semantics without process
+//!   isolation, running supervisors and their workers in threads of the same
+//!   process with [`Backend::Thread`]
+//! - **Embedding**: Spawn the tree on the tokio runtime of a program that
+//!   already runs one with [`Supertree::spawn()`], forking only for the
+//!   supervisors that ask for isolation, and shut it down through its
+//!   [`SupertreeHandle`]
 //! - **Hierarchical structure**: Create a hierarchy of workers and supervisors
 //! - **Restart policies**: Define restart policies for workers
 //! - **Backoff policies**: Define backoff policies for workers
+//! - **Restart strategies**: Restart one, all, or the rest of a supervisor's
+//!   children when one of them stops, like Erlang/OTP's `one_for_one`,
+//!   `one_for_all` and `rest_for_one`
+//! - **Restart intensity**: Limit how often a supervisor's children may be
+//!   restarted, escalating to its own supervisor when the limit is exceeded
+//! - **Graceful shutdown**: Children are stopped in the reverse of their start
+//!   order, according to their shutdown policies, before the tree exits, and
+//!   workers are signaled so they can finish what they're doing
+//! - **Runtime management**: Start, terminate, restart and delete a
+//!   supervisor's workers while the tree is running, through a
+//!   [`SupervisorHandle`]
+//! - **Dynamic supervisors**: Start children on demand from a template, such as
+//!   one worker per tenant or connection, with a [`DynamicSupervisor`]
+//! - **Named children**: Children can be given names, which are unique among
+//!   their siblings, and are addressed by their path in the tree, such as
+//!   `root/db/pool`, in logs and process titles
+//! - **Name registry**: Workers can register values, such as channels, under
+//!   names for the other workers in their supervisor to look up, which are
+//!   removed when the worker stops
+//! - **Mailboxes**: Workers in the same supervisor can send each other typed
+//!   messages through a [`Mailbox`], which buffers messages while its worker is
+//!   restarted
+//! - **GenServers**: Implement request/reply servers with a [`GenServer`],
+//!   whose state is rebuilt each time it's restarted, and call them with a
+//!   [`GenServerClient`]
+//! - **Links and monitors**: Workers can watch the other workers in their
+//!   supervisor stop, or fail along with them, like Erlang/OTP's monitors and
+//!   links
+//! - **Observers**: Get notified when children start, stop, are restarted or
+//!   are given up on with a [`SupervisorObserver`], for alerting and cleanup
+//! - **Event stream**: Subscribe to the [`SupervisionEvent`]s of every
+//!   supervisor in the tree, which are forwarded to the process the tree was
+//!   started from
+//! - **Exit reasons**: Workers can fail with an error, which is told apart from
+//!   finishing normally, stopping when asked to, or panicking
 //!
 //! ## Comparison to Erlang/OTP
 //!
-//! - Unlike Erlang/OTP, this crate does not provide a distributed messaging.
-//!   Another crate (courtesy chez moi) to look at is
+//! - Unlike Erlang/OTP, this crate does not provide distributed messaging:
+//!   workers can only send messages to the other workers of the same tree, on
+//!   the same host. Another crate (courtesy chez moi) to look at is
 //!   [genserver](https://crates.io/crates/genserver), however it does not
 //!   provide IPC.
-//! - This crate does not provide monitoring or tracing, but Tokio itself
-//!   includes a tracing and metrics system that could be used in conjunction
-//!   with this crate.
+//! - Like Erlang/OTP, workers can monitor their siblings, or link to them to
+//!   fail along with them, though only within the same supervisor. The tree's
+//!   starts, stops, restarts and give-ups are reported to
+//!   [`SupervisorObserver`]s and as [`SupervisionEvent`]s, much like the
+//!   progress reports of OTP's supervisors, but there's no equivalent of
+//!   Erlang's tracing of messages and function calls. Tokio itself includes a
+//!   tracing and metrics system that could be used in conjunction with this
+//!   crate.
 //! - Erlang/OTP uses a preemptive green-threads scheduler, while this crate
 //!   uses the Tokio runtime, which is a cooperative multitasking runtime.
-//! - Each separate supervisor within the tree is a separate thread, and there's
-//!   no shared memory or IPC between them (yet, PRs welcome). This is in
-//!   contrast to Erlang/OTP, which uses a shared-nothing architecture.
+//! - Each separate supervisor within the tree is a separate process (unless the
+//!   tree uses [`Backend::Thread`]), and there's no shared memory between them.
+//!   Workers in different supervisors talk through IPC, sending messages that
+//!   are routed along the tree's links, with an [`IpcSender`] and
+//!   [`IpcReceiver`]. This is similar to Erlang/OTP's shared-nothing
+//!   architecture, though messages must be encoded with a [`Codec`] to cross
+//!   process boundaries.
 //! - Erlang/OTP is battle-tested and has been used in production for decades,
 //!   whereas this crate is not.
 //! ## Example
@@ -55,7 +118,7 @@
 //! supervisor, two sub-supervisors, and three workers:
 //!
 //! ```rust
-//! use supertrees::{Restartable, Supertree, Worker};
+//! use supertrees::{BoxError, Context, Restartable, Supertree, Worker};
 //!
 //! #[derive(Debug)]
 //! struct MyWorker {
@@ -74,10 +137,18 @@
 //!     // repeatedly and  any state that needs to be reset should be reset here.
 //!     fn init(
 //!         &self,
-//!     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>> {
+//!         ctx: Context,
+//!     ) -> std::pin::Pin<
+//!         Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>,
+//!     > {
 //!         let num = self.num;
 //!         Box::pin(async move {
 //!             println!("hi, I'm worker num={num} :)");
+//!             // wait until we're asked to stop, at which point we could flush
+//!             // any buffered state before returning
+//!             ctx.shutdown().requested().await;
+//!             // returning an error reports the worker as failed
+//!             Ok(())
 //!         })
 //!     }
 //! }
@@ -96,19 +167,59 @@
 //!
 //! // Now you can start the supervision tree, which will run forever.
 //! // Uncomment the line below to run the supervision tree.
-//! // root.start();
+//! // root.start().unwrap();
 //! ```
 
+pub use dynamic_supervisor::{DynamicSupervisor, DynamicSupervisorHandle};
+pub use error::SupertreeError;
+pub use event::{SupervisionEvent, SupervisionEventKind, SupervisionEvents};
+pub use gen_server::{GenServer, GenServerClient, GenServerError, GenServerWorker};
+pub use handle::{ChildCount, ChildInfo, HandleError, SupervisorHandle};
+pub use intensity::RestartIntensity;
+#[cfg(feature = "bincode")]
+pub use ipc::codec::BincodeCodec;
+#[cfg(feature = "json")]
+pub use ipc::codec::JsonCodec;
+pub use ipc::codec::{BytesCodec, Codec};
+pub use ipc::{IpcError, IpcReceiver, IpcSender};
+pub use observer::{ChildExit, SupervisorObserver};
+pub use process::exit_status::ExitStatus;
+pub use process::exit_summary::ExitSummary;
+pub use spawn::Backend;
+pub use strategy::Strategy;
+pub use supertree_handle::SupertreeHandle;
 pub use supervisor::Supervisor;
+pub use thread_policy::ThreadPolicy;
+pub use worker::Worker;
 pub use worker::backoff_policy::BackoffPolicy;
+pub use worker::context::Context;
+pub use worker::exit_reason::{BoxError, ExitReason};
+pub use worker::link::{Down, LinkError, Monitor};
+pub use worker::mailbox::{Mailbox, MailboxSender, OverflowPolicy, SendError};
+pub use worker::registry::{Registry, RegistryError};
 pub use worker::restartable::{RestartPolicy, Restartable};
-pub use worker::Worker;
+pub use worker::shutdown::Shutdown;
+pub use worker::shutdown_policy::ShutdownPolicy;
 
+mod dynamic_supervisor;
+mod error;
+mod event;
 mod fork;
+mod gen_server;
+mod handle;
+mod intensity;
+mod ipc;
+mod observer;
+mod pidfd;
 mod process;
+mod signal;
+mod spawn;
+mod strategy;
+mod supertree_handle;
 mod supervisor;
 mod syscall;
 mod task;
+mod thread_policy;
 mod worker;
 
 #[derive(Debug)]
@@ -116,6 +227,7 @@ mod worker;
 /// supervisor.
 pub struct Supertree {
     root: Supervisor,
+    thread_policy: ThreadPolicy,
 }
 
 /// Represents a Supervision tree, which is a hierarchical structure used for
@@ -128,10 +240,26 @@ impl Supertree {
     /// Creates a new Supertree with a default root supervisor.
     pub fn new() -> Self {
         Self {
-            root: Supervisor::new(unsafe { libc::getpid() }),
+            root: Supervisor::new(spawn::root_pid() as libc::pid_t, "root".to_string()),
+            thread_policy: ThreadPolicy::default(),
         }
     }
 
+    /// Sets what [`start()`](Self::start) does when it's called from a process
+    /// that already runs other threads. Defaults to [`ThreadPolicy::Refuse`].
+    pub fn with_thread_policy(mut self, thread_policy: ThreadPolicy) -> Self {
+        self.thread_policy = thread_policy;
+        self
+    }
+
+    /// Sets how the supervisors in the Supertree start their children, unless
+    /// a supervisor has its own backend. Defaults to [`Backend::Fork`], or to
+    /// [`Backend::Thread`] for a tree started with [`spawn()`](Self::spawn).
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.root = self.root.with_backend(backend);
+        self
+    }
+
     /// Sets the backoff policy for the Supertree.
     pub fn with_backoff_policy(mut self, backoff_policy: BackoffPolicy) -> Self {
         self.root = self.root.with_backoff_policy(backoff_policy);
@@ -144,10 +272,186 @@ impl Supertree {
         self
     }
 
+    /// Sets the restart strategy for the root supervisor of the Supertree.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.root = self.root.with_strategy(strategy);
+        self
+    }
+
+    /// Sets the restart intensity for the root supervisor of the Supertree.
+    pub fn with_restart_intensity(mut self, intensity: RestartIntensity) -> Self {
+        self.root = self.root.with_restart_intensity(intensity);
+        self
+    }
+
+    /// Sets the observer notified when the children of the supervisors in the
+    /// Supertree start, stop and are restarted, unless a supervisor has its
+    /// own observer.
+    pub fn with_observer(mut self, observer: impl SupervisorObserver + 'static) -> Self {
+        self.root = self.root.with_observer(observer);
+        self
+    }
+
+    /// Adds a named template for workers started at runtime by the root
+    /// supervisor's handle.
+    pub fn add_template<W, F>(mut self, name: &str, template: F) -> Self
+    where
+        W: Worker + 'static,
+        F: Fn(&str) -> W + Send + 'static,
+    {
+        self.root = self.root.add_template(name, template);
+        self
+    }
+
+    /// Returns a handle for managing the root supervisor's children at
+    /// runtime, which can be used from another thread once the tree has been
+    /// started, with [`ThreadPolicy::Allow`]. With a handle, the root
+    /// supervisor keeps running while it has no workers, until the handle
+    /// asks it to shut down.
+    pub fn handle(&mut self) -> std::io::Result<SupervisorHandle> {
+        self.root.handle()
+    }
+
+    /// Subscribes to the events of every supervisor in the Supertree, which
+    /// can be received from another thread while the tree runs, with
+    /// [`ThreadPolicy::Allow`], or once it has stopped. Each call returns a
+    /// separate stream of the same events.
+    pub fn subscribe(&mut self) -> std::io::Result<SupervisionEvents> {
+        self.root.subscribe()
+    }
+
+    /// Adds a dynamic supervisor to the Supertree, whose children are started
+    /// at runtime. The dynamic supervisor is created by applying the given
+    /// closure to a new dynamic supervisor.
+    pub fn add_dynamic_supervisor<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(DynamicSupervisor) -> DynamicSupervisor,
+    {
+        self.root = self.root.add_dynamic_supervisor(f);
+        self
+    }
+
+    /// Adds a dynamic supervisor with the name to the Supertree, like
+    /// [`add_dynamic_supervisor()`](Self::add_dynamic_supervisor).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is invalid or already used, like
+    /// [`add_named_worker()`](Self::add_named_worker).
+    pub fn add_named_dynamic_supervisor<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(DynamicSupervisor) -> DynamicSupervisor,
+    {
+        self.root = self.root.add_named_dynamic_supervisor(name, f);
+        self
+    }
+
     /// Starts the supervision tree, starting the root supervisor and all its
-    /// workers and supervisors.
-    pub fn start(mut self) {
-        self.root.run();
+    /// workers and supervisors. Returns once all of the root supervisor's
+    /// children have stopped, or after they've been shut down when the process
+    /// receives SIGTERM or SIGINT.
+    ///
+    /// Returns how the root supervisor's children stopped, or an error if the
+    /// root supervisor couldn't run them, in which case the children it had
+    /// started have been stopped. Fails without starting any children if the
+    /// process runs other threads, unless they're allowed by the tree's
+    /// [`ThreadPolicy`].
+    ///
+    /// With [`Backend::ReExec`], a process that was re-executed to run a node
+    /// of the tree runs that node instead, and exits once it has stopped
+    /// rather than returning.
+    pub fn start(mut self) -> Result<ExitSummary, SupertreeError> {
+        if let Some(node) = spawn::take_node() {
+            std::process::exit(self.run_node(&node));
+        }
+        self.check_threads(Backend::default())?;
+        let summary = self.root.run()?;
+        if summary.is_intensity_exceeded() {
+            log::debug!("root supervisor exceeded its restart intensity");
+        }
+        Ok(summary)
+    }
+
+    /// Spawns the supervision tree on the tokio runtime it's called from, for
+    /// programs that already run on tokio, and returns a handle for shutting
+    /// it down and waiting for it to stop.
+    ///
+    /// Unless the tree has its own backend, its supervisors run in threads of
+    /// the program's process with [`Backend::Thread`], and their workers run
+    /// on the program's runtime, so that only supervisors with a backend that
+    /// isolates their children start processes. The root supervisor's
+    /// supervision loop blocks while it waits for its children, so it runs on
+    /// a dedicated OS thread named after the root supervisor's path rather
+    /// than as a task, which keeps it from tying up one of the runtime's
+    /// worker threads. The tree leaves the program's signals to the program,
+    /// and is stopped with
+    /// [`SupertreeHandle::shutdown()`]. The errors [`start()`](Self::start)
+    /// would return are returned by [`SupertreeHandle::wait()`].
+    ///
+    /// With [`Backend::ReExec`], a process that was re-executed to run a node
+    /// of the tree runs that node instead, and exits once it has stopped
+    /// rather than returning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it isn't called from within a tokio runtime, or if the
+    /// thread running the root supervisor can't be started.
+    pub fn spawn(mut self) -> SupertreeHandle {
+        if let Some(node) = spawn::take_node() {
+            // the node starts a runtime of its own, which can't be done from
+            // within the program's
+            let code = std::thread::spawn(move || self.run_node(&node))
+                .join()
+                .unwrap_or(process::EXIT_PANIC);
+            std::process::exit(code);
+        }
+        let runtime = tokio::runtime::Handle::current();
+        let (result, receiver) = tokio::sync::oneshot::channel();
+        let path = self.root.path().to_string();
+        let stop = self
+            .check_threads(Backend::Thread)
+            .and_then(|()| ipc::link().map_err(|err| SupertreeError::Signals(path.clone(), err)));
+        let (stop, stop_receiver) = match stop {
+            Ok(stop) => stop,
+            Err(err) => {
+                let _ = result.send(Err(err));
+                return SupertreeHandle::new(None, receiver, None);
+            }
+        };
+        let thread = std::thread::Builder::new()
+            .name(path)
+            .spawn(move || {
+                let _ = result.send(self.root.run_on(runtime, stop_receiver));
+            })
+            .expect("failed to start the root supervisor's thread");
+        SupertreeHandle::new(Some(stop), receiver, Some(thread))
+    }
+
+    /// Runs the node of the tree with the path, in a process that was
+    /// re-executed to run it, returning the exit code for the process.
+    fn run_node(&mut self, node: &str) -> i32 {
+        match self.root.run_node(node) {
+            Some(result) => process::exit_code(node, result),
+            None => {
+                log::error!("no node with path={node} in the tree");
+                process::EXIT_ERROR
+            }
+        }
+    }
+
+    /// Fails if the tree would fork while the process runs other threads,
+    /// given the backend the root supervisor would use without its own,
+    /// unless they're allowed by the tree's [`ThreadPolicy`].
+    fn check_threads(&self, backend: Backend) -> Result<(), SupertreeError> {
+        if self.thread_policy == ThreadPolicy::Refuse && self.root.forks(backend) {
+            if let Some(threads) = process::thread_count().filter(|threads| *threads > 1) {
+                return Err(SupertreeError::Multithreaded(
+                    self.root.path().to_string(),
+                    threads,
+                ));
+            }
+        }
+        Ok(())
     }
 
     /// Adds a worker to the Supertree and returns a new Supertree with the
@@ -157,6 +461,19 @@ impl Supertree {
         self
     }
 
+    /// Adds a worker with the name to the Supertree, which identifies it in
+    /// logs and in the root supervisor's handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is empty, contains a `/`, tab or newline, is
+    /// `watcher`, or is already used by another of the root supervisor's
+    /// children, like [`Supervisor::add_named_worker()`].
+    pub fn add_named_worker(mut self, name: &str, worker: impl Worker + 'static) -> Self {
+        self.root = self.root.add_named_worker(name, worker);
+        self
+    }
+
     /// Adds a supervisor to the Supertree and returns a new Supertree with the
     /// added supervisor. The supervisor is created by applying the given
     /// closure to the current root supervisor.
@@ -167,6 +484,22 @@ impl Supertree {
         self.root = self.root.add_supervisor(f);
         self
     }
+
+    /// Adds a supervisor with the name to the Supertree, like
+    /// [`add_supervisor()`](Self::add_supervisor). Its children's paths start
+    /// with `root/{name}`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is invalid or already used, like
+    /// [`add_named_worker()`](Self::add_named_worker).
+    pub fn add_named_supervisor<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(Supervisor) -> Supervisor,
+    {
+        self.root = self.root.add_named_supervisor(name, f);
+        self
+    }
 }
 
 impl Default for Supertree {
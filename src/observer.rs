@@ -0,0 +1,73 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::{ExitReason, ExitStatus};
+
+/// Represents how a child of a supervisor stopped, as reported to a
+/// [`SupervisorObserver`].
+#[derive(Debug)]
+pub enum ChildExit<'a> {
+    /// A worker stopped for the reason.
+    Worker(&'a ExitReason),
+    /// A child process, such as a child supervisor, exited with the status.
+    Process(ExitStatus),
+}
+
+impl ChildExit<'_> {
+    /// Returns true if the child failed, rather than finishing or stopping
+    /// when asked to.
+    pub fn is_abnormal(&self) -> bool {
+        match self {
+            Self::Worker(reason) => reason.is_abnormal(),
+            Self::Process(exit_status) => exit_status.is_abnormal(),
+        }
+    }
+
+    /// Returns the exit status of the child, which is the exit code the
+    /// reason maps to for a worker.
+    pub fn exit_status(&self) -> ExitStatus {
+        match self {
+            Self::Worker(reason) => reason.exit_status(),
+            Self::Process(exit_status) => *exit_status,
+        }
+    }
+}
+
+impl Display for ChildExit<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Worker(reason) => reason.fmt(f),
+            Self::Process(exit_status) => exit_status.fmt(f),
+        }
+    }
+}
+
+/// Observes the lifecycle of a supervisor's children, for example to raise
+/// alerts or to clean up after a worker that died.
+///
+/// An observer is attached to a supervisor with
+/// [`Supervisor::with_observer()`](crate::Supervisor::with_observer), and is
+/// inherited by its child supervisors that don't have their own. Children are
+/// identified by their path in the tree. Child processes are observed along
+/// with workers, including the watcher process running the supervisor's
+/// workers, whose path ends with `/watcher`.
+///
+/// The hooks are called from the process running the child's supervisor, so
+/// they should return quickly and must not panic.
+pub trait SupervisorObserver: Send + Sync {
+    /// Called when a child starts, including each time it's restarted.
+    fn on_start(&self, _path: &str) {}
+
+    /// Called when a child stops.
+    fn on_exit(&self, _path: &str, _exit: &ChildExit) {}
+
+    /// Called when a child is scheduled to be restarted after the delay.
+    fn on_restart(&self, _path: &str, _delay: Duration) {}
+
+    /// Called when a child that failed won't be restarted under its restart
+    /// policy.
+    fn on_give_up(&self, _path: &str, _exit: &ChildExit) {}
+}
+
+/// Observes nothing, for supervisors without an observer.
+impl SupervisorObserver for () {}
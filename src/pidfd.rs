@@ -0,0 +1,65 @@
+use std::io;
+use std::os::fd::{BorrowedFd, OwnedFd};
+
+use libc::pid_t;
+
+use crate::ExitStatus;
+
+/// Opens a pidfd referring to the child process, which becomes readable once
+/// the process stops. Fails on kernels older than 5.3 and on other platforms,
+/// in which case callers fall back to waiting for SIGCHLD.
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: pid_t) -> io::Result<OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    match unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) }),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pidfd_open(_pid: pid_t) -> io::Result<OwnedFd> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Reaps the child process the pidfd refers to with `waitid(P_PIDFD)`, which
+/// unlike `waitpid()` can't reap another process that was given the child's
+/// pid. Returns `None` without blocking if the process hasn't stopped. Fails
+/// with `EINVAL` on kernels older than 5.4, which open pidfds but can't wait
+/// on them.
+#[cfg(target_os = "linux")]
+pub fn reap(pidfd: BorrowedFd<'_>) -> io::Result<Option<ExitStatus>> {
+    use std::os::fd::AsRawFd;
+
+    use crate::syscall::syscall;
+
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PIDFD,
+                pidfd.as_raw_fd() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG,
+            )
+        };
+        match syscall(result) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+        // without a stopped child to report, the siginfo is left zeroed
+        if unsafe { info.si_pid() } == 0 {
+            return Ok(None);
+        }
+        return Ok(ExitStatus::from_siginfo(info.si_code, unsafe {
+            info.si_status()
+        }));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reap(_pidfd: BorrowedFd<'_>) -> io::Result<Option<ExitStatus>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,91 @@
+use std::fmt::Display;
+
+use super::{EXIT_ERROR, EXIT_INTENSITY_EXCEEDED, EXIT_PANIC, EXIT_SHUTDOWN};
+
+/// Represents how a child process stopped, as reported by `waitpid()`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExitStatus {
+    /// The process exited with the exit code.
+    Exited(i32),
+    /// The process was killed by the signal, and dumped core if
+    /// `core_dumped` is set.
+    Signaled {
+        /// The number of the signal that killed the process.
+        signal: i32,
+        /// Whether the process dumped core.
+        core_dumped: bool,
+    },
+}
+
+impl ExitStatus {
+    /// Decodes a status returned by `waitpid()`, returning `None` if the
+    /// process hasn't stopped.
+    pub(crate) fn from_raw(status: libc::c_int) -> Option<Self> {
+        if libc::WIFEXITED(status) {
+            Some(Self::Exited(libc::WEXITSTATUS(status)))
+        } else if libc::WIFSIGNALED(status) {
+            Some(Self::Signaled {
+                signal: libc::WTERMSIG(status),
+                core_dumped: libc::WCOREDUMP(status),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the `si_code` and `si_status` of the `siginfo_t` filled in by
+    /// `waitid()`, returning `None` if the process hasn't stopped.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn from_siginfo(code: libc::c_int, status: libc::c_int) -> Option<Self> {
+        match code {
+            libc::CLD_EXITED => Some(Self::Exited(status)),
+            libc::CLD_KILLED => Some(Self::Signaled {
+                signal: status,
+                core_dumped: false,
+            }),
+            libc::CLD_DUMPED => Some(Self::Signaled {
+                signal: status,
+                core_dumped: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the exit code, if the process exited rather than being killed
+    /// by a signal.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::Exited(code) => Some(*code),
+            Self::Signaled { .. } => None,
+        }
+    }
+
+    /// Returns true if the process failed, rather than finishing or stopping
+    /// when asked to.
+    pub fn is_abnormal(&self) -> bool {
+        !matches!(self, Self::Exited(0) | Self::Exited(EXIT_SHUTDOWN))
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exited(code) => {
+                let reason = match *code {
+                    0 => "normal",
+                    EXIT_ERROR => "error",
+                    EXIT_INTENSITY_EXCEEDED => "restart intensity exceeded",
+                    EXIT_PANIC => "panic",
+                    EXIT_SHUTDOWN => "shutdown",
+                    _ => "unknown",
+                };
+                write!(f, "exit code {code} ({reason})")
+            }
+            Self::Signaled {
+                signal,
+                core_dumped: true,
+            } => write!(f, "signal {signal} (core dumped)"),
+            Self::Signaled { signal, .. } => write!(f, "signal {signal}"),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::exit_status::ExitStatus;
+use super::{EXIT_INTENSITY_EXCEEDED, EXIT_SHUTDOWN};
+use crate::error::SupertreeError;
+
+/// Represents how a supervision tree stopped, which is returned by
+/// [`Supertree::start()`](crate::Supertree::start).
+#[derive(Debug)]
+pub struct ExitSummary {
+    code: i32,
+    children: Vec<(String, Option<ExitStatus>)>,
+    errors: Vec<SupertreeError>,
+}
+
+impl ExitSummary {
+    pub(crate) fn new(
+        code: i32,
+        children: Vec<(String, Option<ExitStatus>)>,
+        errors: Vec<SupertreeError>,
+    ) -> Self {
+        Self {
+            code,
+            children,
+            errors,
+        }
+    }
+
+    /// Returns the exit code of the root supervisor, which is what a child
+    /// supervisor exits with when it stops the same way. It's non-zero if the
+    /// root supervisor gave up on a child that failed.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// Returns true if the tree stopped because it was asked to shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.code == EXIT_SHUTDOWN
+    }
+
+    /// Returns true if the tree stopped because the root supervisor's
+    /// children were restarted more often than its restart intensity allows.
+    pub fn is_intensity_exceeded(&self) -> bool {
+        self.code == EXIT_INTENSITY_EXCEEDED
+    }
+
+    /// Returns the paths of the root supervisor's child processes, including
+    /// the watcher running its workers, along with how each last stopped.
+    pub fn children(&self) -> impl Iterator<Item = (&str, Option<ExitStatus>)> {
+        self.children
+            .iter()
+            .map(|(path, exit_status)| (path.as_str(), *exit_status))
+    }
+
+    /// Returns the errors reported by the tree's child processes, which
+    /// exited as having failed because of them.
+    pub fn errors(&self) -> &[SupertreeError] {
+        &self.errors
+    }
+}
@@ -1,9 +1,84 @@
+pub mod exit_status;
+pub mod exit_summary;
 pub mod process_group;
 
 use std::fmt::Debug;
+use std::os::fd::RawFd;
 
+use log::{debug, error};
+
+use crate::error::SupertreeError;
 use crate::worker::restartable::Restartable;
 
-pub trait Process: Restartable + Debug {
-    fn start(&mut self);
+/// Exit code of a child process that failed with an error.
+pub const EXIT_ERROR: i32 = 1;
+
+/// Exit code of a child process that stopped its children and gave up after
+/// exceeding its restart intensity.
+pub const EXIT_INTENSITY_EXCEEDED: i32 = 75;
+
+/// Exit code of a child process that panicked, matching the exit code of a
+/// Rust program that panics.
+pub const EXIT_PANIC: i32 = 101;
+
+/// Exit code of a child process that stopped after it was asked to, matching
+/// the convention for a process stopped by SIGTERM.
+pub const EXIT_SHUTDOWN: i32 = 128 + libc::SIGTERM;
+
+pub trait Process: Restartable + Debug + Send {
+    /// Runs the process, returning its exit code, or the error that stopped
+    /// it from running.
+    fn start(&mut self) -> Result<i32, SupertreeError>;
+
+    /// Returns the control sockets served within the process, by name, which
+    /// a re-executed process inherits.
+    fn listeners(&self) -> Vec<(String, RawFd)> {
+        vec![]
+    }
+}
+
+/// Returns the exit code of a child process that ran the node of the tree with
+/// the path, after reporting the error that stopped it, if any.
+pub(crate) fn exit_code(path: &str, result: Result<i32, SupertreeError>) -> i32 {
+    let code = match result {
+        Ok(code) => code,
+        Err(err) => {
+            error!("child process path={path} failed err={err}");
+            err.report();
+            EXIT_ERROR
+        }
+    };
+    debug!("child process exiting with code={code}");
+    code
+}
+
+/// The longest process title the kernel keeps, not counting the trailing nul.
+#[cfg(target_os = "linux")]
+const MAX_TITLE_LEN: usize = 15;
+
+/// Sets the title of the current process to the child's path in the tree, so
+/// that it can be told apart in tools like `ps` and `top`. The title is
+/// truncated to the end of the path, which names the child itself.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_title(path: &str) {
+    let mut start = path.len().saturating_sub(MAX_TITLE_LEN);
+    while !path.is_char_boundary(start) {
+        start += 1;
+    }
+    if let Ok(title) = std::ffi::CString::new(&path[start..]) {
+        unsafe {
+            libc::prctl(libc::PR_SET_NAME, title.as_ptr());
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_title(_path: &str) {}
+
+/// Returns how many threads the current process runs, if the platform lists
+/// them in `/proc`.
+pub(crate) fn thread_count() -> Option<usize> {
+    std::fs::read_dir("/proc/self/task")
+        .ok()
+        .map(|tasks| tasks.count())
 }
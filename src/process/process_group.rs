@@ -1,129 +1,965 @@
-use std::collections::HashMap;
+use std::ops::Range;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixDatagram;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
 use libc::pid_t;
-use log::debug;
+use log::{debug, error};
 
-use super::Process;
-use crate::fork::{fork, ForkResult};
+use super::exit_status::ExitStatus;
+use super::exit_summary::ExitSummary;
+use super::{
+    EXIT_ERROR, EXIT_INTENSITY_EXCEEDED, EXIT_PANIC, EXIT_SHUTDOWN, Process, exit_code, set_title,
+};
+use crate::error::{self, SupertreeError};
+use crate::event::{self, Notifier};
+use crate::fork::{ForkResult, fork};
+use crate::intensity::{RestartBudget, RestartIntensity};
+use crate::ipc::{self, MAX_FRAME_LEN};
+use crate::observer::ChildExit;
+use crate::pidfd::{self, pidfd_open};
+use crate::signal::{self, ShutdownSignals};
+use crate::spawn::{self, Backend};
 use crate::syscall::syscall;
 use crate::worker::backoff::{Backoff, BackoffResult};
+use crate::worker::backoff_policy::BackoffPolicy;
+use crate::worker::restartable::{RestartPolicy, Restartable};
+use crate::{ShutdownPolicy, Strategy};
+
+/// How often a child is polled while waiting for it to stop, within its
+/// shutdown timeout or without SIGCHLD to wake the group up.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The policies of a child, which are kept by the group while the child's
+/// process runs in a thread.
+#[derive(Debug)]
+struct Policies {
+    restart_policy: RestartPolicy,
+    backoff_policy: BackoffPolicy,
+    shutdown_policy: ShutdownPolicy,
+}
+
+impl Policies {
+    fn of(process: &dyn Process) -> Self {
+        Self {
+            restart_policy: process.restart_policy(),
+            backoff_policy: process.backoff_policy(),
+            shutdown_policy: process.shutdown_policy(),
+        }
+    }
+}
+
+impl Restartable for Policies {
+    fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+
+    fn backoff_policy(&self) -> BackoffPolicy {
+        self.backoff_policy
+    }
+
+    fn shutdown_policy(&self) -> ShutdownPolicy {
+        self.shutdown_policy
+    }
+}
+
+/// How a running child runs.
+enum Running {
+    /// In a process, along with a pidfd for it if the platform supports them.
+    Process(pid_t, Option<OwnedFd>),
+    /// In a thread, which hands the child's process back once it stops, along
+    /// with the socket the thread wakes the group up on once it has.
+    Thread(JoinHandle<(Box<dyn Process>, i32)>, UnixDatagram),
+}
+
+impl Running {
+    fn process(child_pid: pid_t) -> Self {
+        let pidfd = match pidfd_open(child_pid) {
+            Ok(pidfd) => Some(pidfd),
+            Err(err) => {
+                debug!("pidfd_open failed, waiting for SIGCHLD instead err={err}");
+                None
+            }
+        };
+        Self::Process(child_pid, pidfd)
+    }
+
+    /// Returns the fd that becomes readable once the child stops, if there's
+    /// one.
+    fn wake_fd(&self) -> Option<BorrowedFd<'_>> {
+        match self {
+            Self::Process(_, pidfd) => pidfd.as_ref().map(|pidfd| pidfd.as_fd()),
+            Self::Thread(_, exited) => Some(exited.as_fd()),
+        }
+    }
+}
+
+/// The frames addressed to the group itself, rather than to a worker.
+#[derive(Default)]
+struct Controls {
+    /// The parent asked the group to stop, for a group running in a thread.
+    stop: bool,
+    /// The watcher asked for its workers to be restarted.
+    restarts: Vec<ipc::Restart>,
+    /// The watcher asked for the child supervisors with the names to be
+    /// stopped, for the supervisor's handle.
+    terminate: Vec<String>,
+    /// The watcher asked for the child supervisors with the names to be
+    /// started again, for the supervisor's handle.
+    start: Vec<String>,
+}
+
+/// A child of the group, along with how it last stopped.
+struct Child {
+    /// The path of the child in the tree, such as `root/db`.
+    path: String,
+    /// The position of the child among its supervisor's children, in the
+    /// order they were added, which is the position of the first worker for
+    /// the watcher.
+    position: usize,
+    /// The child's process, which is moved into the thread running it while
+    /// it runs in one.
+    process: Option<Box<dyn Process>>,
+    backoff: Backoff<Policies>,
+    running: Option<Running>,
+    /// The pid the child last ran with, which is kept after it stops, and is
+    /// the group's own pid for a child running in a thread.
+    last_pid: pid_t,
+    /// The link to the child, which is kept after it stops so that the frames
+    /// it sent before stopping are still routed.
+    link: Option<UnixDatagram>,
+    exit_status: Option<ExitStatus>,
+    /// When the child is due to be restarted, while it's backing off.
+    restart_at: Option<Instant>,
+    /// Whether the child was terminated through the supervisor's handle, in
+    /// which case it isn't restarted until it's started again through it.
+    terminated: bool,
+}
+
+impl Child {
+    fn start(&mut self, notifier: &Notifier, backend: Backend) -> Result<pid_t, SupertreeError> {
+        let process = self
+            .process
+            .as_mut()
+            .expect("a child that isn't running has its process");
+        let result = match backend {
+            Backend::Fork => ProcessGroup::fork(&self.path, process)
+                .map(|(child_pid, link)| (child_pid, Running::process(child_pid), link)),
+            Backend::ReExec => ProcessGroup::reexec(&self.path, process.as_ref())
+                .map(|(child_pid, link)| (child_pid, Running::process(child_pid), link)),
+            Backend::Thread => ProcessGroup::spawn_thread(&self.path, &mut self.process)
+                .map(|(running, link)| (std::process::id() as pid_t, running, link)),
+        };
+        let (child_pid, running, link) =
+            result.map_err(|err| SupertreeError::Fork(self.path.clone(), err))?;
+        self.link = Some(link);
+        self.running = Some(running);
+        self.last_pid = child_pid;
+        notifier.started(&self.path, child_pid as u32);
+        Ok(child_pid)
+    }
+
+    /// Stops the child according to its shutdown policy, if it's running.
+    fn stop(&mut self, notifier: &Notifier) {
+        let shutdown_policy = self.backoff.shutdown_policy();
+        self.exit_status = match self.running.take() {
+            None => return,
+            Some(Running::Process(child_pid, _)) => {
+                ProcessGroup::stop_child(child_pid, shutdown_policy)
+            }
+            Some(Running::Thread(handle, _)) => {
+                let (process, exit_status) = ProcessGroup::stop_thread(
+                    &self.path,
+                    self.link.as_ref(),
+                    handle,
+                    shutdown_policy,
+                );
+                self.process = Some(process);
+                Some(exit_status)
+            }
+        };
+        if let Some(exit_status) = self.exit_status {
+            notifier.exited(
+                &self.path,
+                self.last_pid as u32,
+                &ChildExit::Process(exit_status),
+            );
+        }
+    }
+}
 
 pub struct ProcessGroup {
-    processes: Vec<Box<dyn Process>>,
+    path: String,
+    processes: Vec<(String, Box<dyn Process>)>,
+    /// The positions of the processes among the supervisor's children, which
+    /// are their indices unless they're set.
+    positions: Vec<usize>,
+    strategy: Strategy,
+    intensity: Option<RestartIntensity>,
+    watcher: Option<usize>,
+    notifier: Notifier,
+    backend: Backend,
+    /// Whether the group runs in a thread of its parent's process, rather
+    /// than in a process of its own, in which case it's asked to stop by its
+    /// parent instead of by signals.
+    in_thread: bool,
+    /// The socket the handle of a tree spawned on a runtime asks the root
+    /// group to stop on, in place of the signals it leaves to the program.
+    stop: Option<UnixDatagram>,
 }
 
 impl ProcessGroup {
-    pub fn new() -> Self {
-        Self { processes: vec![] }
+    pub fn new(path: String, strategy: Strategy, intensity: Option<RestartIntensity>) -> Self {
+        Self {
+            path,
+            processes: vec![],
+            positions: vec![],
+            strategy,
+            intensity,
+            watcher: None,
+            notifier: Notifier::default(),
+            backend: Backend::default(),
+            in_thread: false,
+            stop: None,
+        }
+    }
+
+    /// Runs the group in a thread of its parent's process, which leaves the
+    /// process's signals to the group running in it.
+    pub(crate) fn with_in_thread(mut self, in_thread: bool) -> Self {
+        self.in_thread = in_thread;
+        self
+    }
+
+    /// Stops the group once a datagram is received on the socket.
+    pub(crate) fn with_stop(mut self, stop: UnixDatagram) -> Self {
+        self.stop = Some(stop);
+        self
     }
 
-    pub fn add_process(&mut self, process: Box<dyn Process>) {
-        self.processes.push(process);
+    /// Sets the positions of the processes among the supervisor's children,
+    /// which the strategy restarts them by, along with the workers of the
+    /// watcher.
+    pub(crate) fn with_positions(mut self, positions: Vec<usize>) -> Self {
+        self.positions = positions;
+        self
     }
 
-    fn fork(process: &mut Box<dyn Process>) -> io::Result<pid_t> {
-        debug!("forking new child process");
+    /// Sets how the children are started.
+    pub(crate) fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Notifies the observer and the event subscribers of the lifecycle of
+    /// the children.
+    pub(crate) fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    pub fn add_process(&mut self, path: String, process: Box<dyn Process>) {
+        self.processes.push((path, process));
+    }
+
+    /// Inserts the watcher running the supervisor's workers at the given
+    /// position. The watcher counts worker restarts against the supervisor's
+    /// restart intensity, so when it gives up the whole group gives up.
+    pub fn insert_watcher(&mut self, index: usize, path: String, watcher: Box<dyn Process>) {
+        self.processes.insert(index, (path, watcher));
+        self.watcher = Some(index);
+    }
+
+    /// Takes the watcher out of the group, in a process that was re-executed
+    /// to run it.
+    pub(crate) fn into_watcher(mut self) -> Option<Box<dyn Process>> {
+        let index = self.watcher?;
+        Some(self.processes.remove(index).1)
+    }
+
+    /// Forks a child running the process, returning its pid along with the
+    /// link to it, which is created before forking.
+    fn fork(path: &str, process: &mut Box<dyn Process>) -> io::Result<(pid_t, UnixDatagram)> {
+        debug!("forking new child process path={path}");
+        let (link, uplink) = ipc::link()?;
         let fork_result = fork()?;
 
         match fork_result {
             ForkResult::Child => {
-                process.start();
-                Ok(0)
+                signal::reset();
+                set_title(path);
+                drop(link);
+                ipc::set_uplink(uplink);
+                // the child exits rather than returning into the caller's
+                // code
+                std::process::exit(exit_code(path, process.start()));
             }
             ForkResult::Parent(child_pid) => {
-                Self::handle_child(child_pid)?;
-                Ok(child_pid)
+                debug!("child path={path} pid={child_pid} started");
+                Ok((child_pid, link))
             }
         }
     }
 
-    fn handle_child(child_pid: pid_t) -> io::Result<()> {
-        debug!("child pid={child_pid} started");
-        unsafe {
-            // set process group ID for current process
-            syscall(libc::setpgid(0, 0))?;
-            // set process group ID for child process
-            syscall(libc::setpgid(child_pid, libc::getpid()))?;
+    /// Starts a child running the process by executing the program again,
+    /// returning its pid along with the link to it. The child inherits the
+    /// other end of the link, along with the control sockets served within
+    /// the process.
+    fn reexec(path: &str, process: &dyn Process) -> io::Result<(pid_t, UnixDatagram)> {
+        debug!("re-executing new child process path={path}");
+        let (link, uplink) = ipc::link()?;
+        let child_pid = spawn::reexec(path, &uplink, &process.listeners())?;
+        debug!("child path={path} pid={child_pid} started");
+        Ok((child_pid, link))
+    }
+
+    /// Starts a thread running the process, returning it along with the link
+    /// to it. The process is only moved into the thread once it has started,
+    /// so that it's kept if the thread can't be started.
+    fn spawn_thread(
+        path: &str,
+        process: &mut Option<Box<dyn Process>>,
+    ) -> io::Result<(Running, UnixDatagram)> {
+        debug!("starting new child thread path={path}");
+        let (link, uplink) = ipc::link()?;
+        let (exited, wake) = UnixDatagram::pair()?;
+        exited.set_nonblocking(true)?;
+        let (send_process, receive_process) = mpsc::channel::<Box<dyn Process>>();
+        let thread_path = path.to_string();
+        let handle = thread::Builder::new()
+            .name(path.to_string())
+            .spawn(move || {
+                ipc::set_thread_uplink(Arc::new(uplink));
+                let mut process = receive_process
+                    .recv()
+                    .expect("the process is sent once the thread has started");
+                // a panic takes down the child, like it takes down a process
+                let code = match panic::catch_unwind(AssertUnwindSafe(|| process.start())) {
+                    Ok(result) => exit_code(&thread_path, result),
+                    Err(_) => EXIT_PANIC,
+                };
+                let _ = wake.send(&[0]);
+                (process, code)
+            })?;
+        if let Some(process) = process.take() {
+            let _ = send_process.send(process);
+        }
+        Ok((Running::Thread(handle, exited), link))
+    }
+
+    /// Asks the child running in a thread to stop, and waits for it to,
+    /// however long it takes, since a thread can't be killed. Returns the
+    /// child's process along with how it stopped.
+    fn stop_thread(
+        path: &str,
+        link: Option<&UnixDatagram>,
+        handle: JoinHandle<(Box<dyn Process>, i32)>,
+        shutdown_policy: ShutdownPolicy,
+    ) -> (Box<dyn Process>, ExitStatus) {
+        debug!("stopping child thread path={path} with shutdown_policy={shutdown_policy:?}");
+        let result = ipc::encode_frame(ipc::SHUTDOWN, &[]).and_then(|frame| {
+            let link = link.ok_or(ipc::IpcError::Disconnected)?;
+            link.send(&frame).map_err(ipc::IpcError::Io)
+        });
+        if let Err(err) = result {
+            error!("couldn't ask child thread path={path} to stop err={err}");
+        }
+        let timeout = match shutdown_policy {
+            ShutdownPolicy::BrutalKill => Some(Duration::ZERO),
+            ShutdownPolicy::Timeout(timeout) => Some(timeout),
+            ShutdownPolicy::Infinity => None,
         };
-        Ok(())
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(SHUTDOWN_POLL_INTERVAL);
+            }
+            if !handle.is_finished() {
+                error!(
+                    "child thread path={path} didn't stop within timeout={timeout:?}, waiting for \
+                     it"
+                );
+            }
+        }
+        Self::join(handle)
+    }
+
+    /// Waits for the thread running a child to finish, returning the child's
+    /// process along with how it stopped.
+    fn join(handle: JoinHandle<(Box<dyn Process>, i32)>) -> (Box<dyn Process>, ExitStatus) {
+        match handle.join() {
+            Ok((process, code)) => (process, ExitStatus::Exited(code)),
+            // the process's own panics are caught in the thread
+            Err(payload) => panic::resume_unwind(payload),
+        }
     }
 
-    fn send_sigterm(child_pid: pid_t) {
-        debug!("sending SIGTERM to {child_pid}");
+    fn send_signal(child_pid: pid_t, signal: libc::c_int) {
+        debug!("sending signal={signal} to {child_pid}");
         unsafe {
-            libc::kill(child_pid, libc::SIGTERM);
+            libc::kill(child_pid, signal);
         }
     }
 
-    fn terminate<P>(processes: &mut HashMap<pid_t, P>) {
-        debug!("terminating remaining children");
-        processes
+    /// Waits for the child to stop, returning how it stopped once it's been
+    /// reaped. When `block` is false, returns `None` immediately if the child
+    /// is still running.
+    fn reap(child_pid: pid_t, block: bool) -> io::Result<Option<ExitStatus>> {
+        let options = if block { 0 } else { libc::WNOHANG };
+        loop {
+            let mut status: libc::c_int = 0;
+            match unsafe { syscall(libc::waitpid(child_pid, &mut status, options)) } {
+                Ok(0) => return Ok(None),
+                Ok(_) => match ExitStatus::from_raw(status) {
+                    Some(exit_status) => return Ok(Some(exit_status)),
+                    None => continue,
+                },
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Stops the child according to its shutdown policy, and reaps it so that
+    /// its exit isn't handled again by the reaping loop.
+    fn stop_child(child_pid: pid_t, shutdown_policy: ShutdownPolicy) -> Option<ExitStatus> {
+        debug!("stopping child pid={child_pid} with shutdown_policy={shutdown_policy:?}");
+        match shutdown_policy {
+            ShutdownPolicy::BrutalKill => {
+                Self::send_signal(child_pid, libc::SIGKILL);
+            }
+            ShutdownPolicy::Timeout(timeout) => {
+                Self::send_signal(child_pid, libc::SIGTERM);
+                let deadline = Instant::now() + timeout;
+                while Instant::now() < deadline {
+                    match Self::reap(child_pid, false) {
+                        Ok(None) => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+                        Ok(exit_status) => return exit_status,
+                        Err(err) => {
+                            debug!("waitpid on pid={child_pid} failed err={err}");
+                            return None;
+                        }
+                    }
+                }
+                debug!("child pid={child_pid} didn't stop within timeout={timeout:?}");
+                Self::send_signal(child_pid, libc::SIGKILL);
+            }
+            ShutdownPolicy::Infinity => {
+                Self::send_signal(child_pid, libc::SIGTERM);
+            }
+        }
+        match Self::reap(child_pid, true) {
+            Ok(exit_status) => exit_status,
+            Err(err) => {
+                debug!("waitpid on pid={child_pid} failed err={err}");
+                None
+            }
+        }
+    }
+
+    /// Forwards the frames waiting on the links to the children and to the
+    /// parent process group towards the workers they're addressed to.
+    /// Returns the frames addressed to the group itself.
+    fn route(
+        path: &str,
+        notifier: &Notifier,
+        errors: &mut Vec<SupertreeError>,
+        watcher: Option<usize>,
+        children: &[Child],
+        uplink: Option<&UnixDatagram>,
+        buf: &mut [u8],
+    ) -> Controls {
+        let mut controls = Controls::default();
+        let links = children
             .iter()
-            .filter_map(|(child_pid, _)| match child_pid {
-                0 => None,
-                _ => Some(child_pid),
+            .enumerate()
+            .filter_map(|(index, child)| child.link.as_ref().map(|link| (link, Some(index))))
+            .chain(uplink.map(|uplink| (uplink, None)));
+        for (link, from) in links {
+            let from_parent = from.is_none();
+            loop {
+                let len = match link.recv(buf) {
+                    Ok(len) => len,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        debug!("failed to receive frame err={err}");
+                        break;
+                    }
+                };
+                let frame = &buf[..len];
+                let Some((destination, payload)) = ipc::decode_frame(frame) else {
+                    debug!("dropping malformed frame of {len} bytes");
+                    continue;
+                };
+                if destination == ipc::SHUTDOWN && from_parent {
+                    controls.stop = true;
+                    continue;
+                }
+                if destination == ipc::RESTART && from.is_some() && from == watcher {
+                    match ipc::Restart::decode(payload) {
+                        Some(restart) => controls.restarts.push(restart),
+                        None => debug!("dropping malformed restart of {} bytes", payload.len()),
+                    }
+                    continue;
+                }
+                if (destination == ipc::TERMINATE || destination == ipc::START)
+                    && from.is_some()
+                    && from == watcher
+                {
+                    match std::str::from_utf8(payload) {
+                        Ok(name) if destination == ipc::TERMINATE => {
+                            controls.terminate.push(name.to_string())
+                        }
+                        Ok(name) => controls.start.push(name.to_string()),
+                        Err(_) => debug!("dropping malformed child name of {len} bytes"),
+                    }
+                    continue;
+                }
+                // events and errors end up in the process the tree was
+                // started from
+                if destination == event::DESTINATION && uplink.is_none() {
+                    notifier.deliver(payload);
+                    continue;
+                }
+                if destination == error::DESTINATION && uplink.is_none() {
+                    match SupertreeError::decode(payload) {
+                        Some(err) => {
+                            error!("child process reported err={err}");
+                            errors.push(err);
+                        }
+                        None => debug!("dropping malformed error of {} bytes", payload.len()),
+                    }
+                    continue;
+                }
+                // frames go up until they reach a supervisor that the
+                // destination is within, and then down towards it, ending
+                // with the watcher running the destination worker
+                let supervisor = children.iter().enumerate().find(|(index, child)| {
+                    Some(*index) != watcher && ipc::is_within(destination, &child.path)
+                });
+                let next = if !ipc::is_within(destination, path) {
+                    uplink.filter(|_| !from_parent)
+                } else if let Some((_, child)) = supervisor {
+                    child.link.as_ref()
+                } else if destination
+                    .rsplit_once('/')
+                    .is_some_and(|(parent, _)| parent == path)
+                {
+                    watcher.and_then(|index| children[index].link.as_ref())
+                } else {
+                    None
+                };
+                let Some(next) = next else {
+                    debug!("dropping frame for unknown destination={destination}");
+                    continue;
+                };
+                if let Err(err) = next.send(frame) {
+                    debug!("dropping frame for destination={destination} err={err}");
+                }
+            }
+        }
+        controls
+    }
+
+    /// Restarts the children in the range of positions after the delay,
+    /// along with the child at the index that stopped, if there's one. The
+    /// running children are stopped in the reverse of their start order, and
+    /// the watcher is told to restart its workers in the range, along with
+    /// the worker with the name that stopped, if there's one.
+    fn restart(
+        &self,
+        children: &mut [Child],
+        stopped: Option<usize>,
+        worker: &str,
+        range: Range<usize>,
+        delay: Duration,
+    ) {
+        let notifier = &self.notifier;
+        // the watcher's workers are restarted by the watcher, unless it's
+        // the watcher that stopped
+        let restart: Vec<usize> = (0..children.len())
+            .filter(|sibling| {
+                Some(*sibling) == stopped
+                    || (Some(*sibling) != self.watcher
+                        && children[*sibling].running.is_some()
+                        && range.contains(&children[*sibling].position))
             })
-            .for_each(|child_pid| Self::send_sigterm(*child_pid));
-        processes.clear();
+            .collect();
+        for sibling in restart.iter().rev() {
+            children[*sibling].stop(notifier);
+        }
+        let restart_at = Instant::now() + delay;
+        for sibling in restart {
+            let sibling = &mut children[sibling];
+            debug!(
+                "retrying child path={} after delay={delay:?}, last_exit_status={:?}",
+                sibling.path, sibling.exit_status
+            );
+            notifier.restart_scheduled(&sibling.path, sibling.last_pid as u32, delay);
+            sibling.restart_at = Some(restart_at);
+        }
+        let Some(watcher) = self.watcher.filter(|watcher| Some(*watcher) != stopped) else {
+            return;
+        };
+        if worker.is_empty() && self.strategy == Strategy::OneForOne {
+            return;
+        }
+        let restart = ipc::Restart {
+            name: worker.to_string(),
+            delay,
+            range,
+        };
+        let result = children[watcher]
+            .link
+            .as_ref()
+            .ok_or(ipc::IpcError::Disconnected)
+            .and_then(|link| restart.send(link));
+        if let Err(err) = result {
+            error!("couldn't restart workers of path={} err={err}", self.path);
+        }
+    }
+
+    /// Returns the index of the child supervisor with the name.
+    fn supervisor_index(&self, children: &[Child], name: &str) -> Option<usize> {
+        children.iter().enumerate().position(|(index, child)| {
+            Some(index) != self.watcher
+                && child
+                    .path
+                    .rsplit_once('/')
+                    .is_some_and(|(parent, child)| parent == self.path && child == name)
+        })
+    }
+
+    /// Tells the watcher which of the child supervisors have started or
+    /// stopped since it was last told, for the supervisor's handle.
+    fn report(&self, children: &[Child], reported: &mut [bool]) {
+        let Some(link) = self.watcher.and_then(|index| children[index].link.as_ref()) else {
+            return;
+        };
+        for (index, child) in children.iter().enumerate() {
+            let running = child.running.is_some() || child.restart_at.is_some();
+            if Some(index) == self.watcher || reported[index] == running {
+                continue;
+            }
+            let state = ipc::ChildState {
+                name: child
+                    .path
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                running,
+            };
+            match state.send(link) {
+                Ok(()) => reported[index] = running,
+                Err(err) => debug!(
+                    "couldn't tell watcher about child path={} err={err}",
+                    child.path
+                ),
+            }
+        }
     }
 
-    pub fn run(self) {
+    /// Stops the running children in the reverse of their start order.
+    fn shutdown(children: &mut [Child], notifier: &Notifier) {
+        debug!("shutting down remaining children");
+        for child in children.iter_mut().rev() {
+            child.restart_at = None;
+            child.stop(notifier);
+        }
+    }
+
+    /// Reaps the first running child that has stopped, returning its index
+    /// along with how it stopped.
+    fn reap_stopped(children: &mut [Child]) -> io::Result<Option<(usize, ExitStatus)>> {
+        for (index, child) in children.iter_mut().enumerate() {
+            let exit_status = match &child.running {
+                Some(Running::Process(child_pid, pidfd)) => {
+                    let child_pid = *child_pid;
+                    // a child with a pidfd is reaped through it, so that it's
+                    // never confused with a process that reused its pid
+                    let reaped = match pidfd {
+                        Some(pidfd) => match pidfd::reap(pidfd.as_fd()) {
+                            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+                                Self::reap(child_pid, false)
+                            }
+                            reaped => reaped,
+                        },
+                        None => Self::reap(child_pid, false),
+                    };
+                    let Some(exit_status) = reaped? else {
+                        continue;
+                    };
+                    debug!("reaped child pid={child_pid}");
+                    child.running = None;
+                    exit_status
+                }
+                Some(Running::Thread(handle, _)) if handle.is_finished() => {
+                    let Some(Running::Thread(handle, _)) = child.running.take() else {
+                        unreachable!("the child runs in a thread");
+                    };
+                    debug!("joined child thread path={}", child.path);
+                    let (process, exit_status) = Self::join(handle);
+                    child.process = Some(process);
+                    exit_status
+                }
+                _ => continue,
+            };
+            return Ok(Some((index, exit_status)));
+        }
+        Ok(None)
+    }
+
+    /// Runs the process group until all of its children have stopped,
+    /// returning how they stopped along with the exit code for the process
+    /// running the group. The children's processes are kept, so that a group
+    /// running in a thread can be run again when it's restarted.
+    pub fn run(&mut self) -> Result<ExitSummary, SupertreeError> {
         let count = self.processes.len();
-        debug!("starting process group with {count} processes");
+        debug!(
+            "starting process group with {count} processes and strategy={:?}",
+            self.strategy
+        );
+        // a group running in a thread leaves the process's signals to the
+        // group running in the process
+        let signals = match self.in_thread {
+            true => None,
+            false => Some(
+                ShutdownSignals::install()
+                    .map_err(|err| SupertreeError::Signals(self.path.clone(), err))?,
+            ),
+        };
+        let mut children: Vec<Child> = std::mem::take(&mut self.processes)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (path, process))| Child {
+                path,
+                position: self.positions.get(index).copied().unwrap_or(index),
+                backoff: Backoff::new(Box::new(Policies::of(process.as_ref()))),
+                process: Some(process),
+                running: None,
+                last_pid: 0,
+                link: None,
+                exit_status: None,
+                restart_at: None,
+                terminated: false,
+            })
+            .collect();
+        let result = self.supervise(signals.as_ref(), &mut children);
+        self.processes = children
+            .into_iter()
+            .filter_map(|child| Some((child.path, child.process?)))
+            .collect();
+        result
+    }
 
-        let mut processes: HashMap<pid_t, Backoff<dyn Process>> = HashMap::new();
+    /// Starts the children and restarts them as they stop, until they've all
+    /// stopped.
+    fn supervise(
+        &self,
+        signals: Option<&ShutdownSignals>,
+        children: &mut [Child],
+    ) -> Result<ExitSummary, SupertreeError> {
+        let strategy = self.strategy;
+        let mut budget = RestartBudget::new(self.intensity);
+        let uplink = ipc::uplink();
+        let mut buf = vec![0; MAX_FRAME_LEN];
+        let mut errors = vec![];
+        let mut stop_requested = false;
+        // the watcher takes its supervisor's children to be running when
+        // it's started
+        let mut reported = vec![true; children.len()];
 
-        for mut process in self.processes.into_iter() {
-            let child_pid = Self::fork(&mut process).expect("fork failed");
-            if child_pid != 0 {
-                processes.insert(child_pid, Backoff::new(process));
-            } else {
-                // pid 0 are forked children returning/exiting, as such we can ignore
-                // them and return early.
-                return;
+        let notifier = &self.notifier;
+        let backend = self.backend;
+        for index in 0..children.len() {
+            if let Err(err) = children[index].start(notifier, backend) {
+                Self::shutdown(children, notifier);
+                return Err(err);
             }
         }
 
-        let pid = unsafe { libc::getpid() };
-        while !processes.is_empty() {
-            let mut status: libc::c_int = 0;
-            debug!("waiting on children from pid={pid}");
-            match unsafe { syscall(libc::waitpid(-pid, &mut status, 0)) } {
-                Ok(ret) => {
-                    debug!("waitpid returned ret={ret} status={status}");
-                    let exited = libc::WIFEXITED(status);
-                    let exit_status = libc::WEXITSTATUS(status);
-                    let signaled = libc::WIFSIGNALED(status);
-                    if signaled {
-                        let signal = libc::WTERMSIG(status);
-                        debug!("waitpid interrupted by signal={signal}");
-                        Self::terminate(&mut processes);
-                    } else if exited {
-                        debug!("child pid={ret} exited with exit_status={exit_status}");
-                        Self::send_sigterm(ret);
-                        match processes.remove(&ret) {
-                            Some(mut process) => {
-                                if let BackoffResult::RetryAfterDelay(delay) = process.maybe_delay()
-                                {
-                                    debug!("retrying child pid={ret} after delay={delay:?}");
-                                    thread::sleep(delay);
-                                    let child_pid = Self::fork(&mut process).expect("fork failed");
-                                    processes.insert(child_pid, process);
+        let code = 'supervise: loop {
+            if let Some(stop) = &self.stop {
+                stop_requested |= stop.recv(&mut [0]).is_ok();
+            }
+            if stop_requested || signals.is_some_and(ShutdownSignals::requested) {
+                debug!("shutdown requested, stopping process group");
+                notifier.shutdown(&self.path);
+                Self::shutdown(children, notifier);
+                break EXIT_SHUTDOWN;
+            }
+            // children whose backoff delay has passed are restarted in their
+            // start order
+            let now = Instant::now();
+            for index in 0..children.len() {
+                let child = &mut children[index];
+                if child.restart_at.is_some_and(|restart_at| restart_at <= now) {
+                    child.restart_at = None;
+                    if let Err(err) = child.start(notifier, backend) {
+                        Self::shutdown(children, notifier);
+                        return Err(err);
+                    }
+                    if self.watcher == Some(index) {
+                        reported.fill(true);
+                    }
+                }
+            }
+            let controls = Self::route(
+                &self.path,
+                notifier,
+                &mut errors,
+                self.watcher,
+                children,
+                uplink.as_deref(),
+                &mut buf,
+            );
+            if controls.stop {
+                stop_requested = true;
+                continue;
+            }
+            // the watcher asks for its workers to be restarted, since the
+            // strategy may restart the other children along with them
+            for restart in controls.restarts {
+                if !budget.record() {
+                    debug!("restart intensity exceeded, stopping process group");
+                    notifier.intensity_exceeded(&self.path);
+                    Self::shutdown(children, notifier);
+                    break 'supervise EXIT_INTENSITY_EXCEEDED;
+                }
+                let range = strategy.restart_range(restart.range.start, usize::MAX);
+                self.restart(children, None, &restart.name, range, restart.delay);
+            }
+            // the supervisor's handle terminates and starts its child
+            // supervisors through the watcher
+            for name in controls.terminate {
+                let Some(index) = self.supervisor_index(children, &name) else {
+                    continue;
+                };
+                let child = &mut children[index];
+                child.restart_at = None;
+                child.terminated = true;
+                child.stop(notifier);
+            }
+            for name in controls.start {
+                let Some(index) = self.supervisor_index(children, &name) else {
+                    continue;
+                };
+                let child = &mut children[index];
+                if child.running.is_some() {
+                    continue;
+                }
+                child.restart_at = None;
+                child.terminated = false;
+                if let Err(err) = child.start(notifier, backend) {
+                    Self::shutdown(children, notifier);
+                    return Err(err);
+                }
+            }
+            let next_restart = children.iter().filter_map(|child| child.restart_at).min();
+            if next_restart.is_none() && children.iter().all(|child| child.running.is_none()) {
+                // the group fails if it gave up on any child that failed,
+                // rather than one that was terminated through the handle
+                let failed = children.iter().any(|child| {
+                    !child.terminated
+                        && child.exit_status.is_some_and(|status| status.is_abnormal())
+                });
+                break if failed { EXIT_ERROR } else { 0 };
+            }
+            let (index, exit_status) = match Self::reap_stopped(children) {
+                Ok(Some(stopped)) => stopped,
+                Ok(None) => {
+                    self.report(children, &mut reported);
+                    // children without a pidfd wake the wait up with SIGCHLD,
+                    // and frames waiting to be routed wake it up too
+                    let fds: Vec<BorrowedFd> = children
+                        .iter()
+                        .flat_map(|child| {
+                            [
+                                child.running.as_ref().and_then(Running::wake_fd),
+                                child.link.as_ref().map(|link| link.as_fd()),
+                            ]
+                        })
+                        .flatten()
+                        .chain(uplink.as_ref().map(|uplink| uplink.as_fd()))
+                        .chain(self.stop.as_ref().map(|stop| stop.as_fd()))
+                        .collect();
+                    let timeout = next_restart.map(|at| at.saturating_duration_since(now));
+                    match signals {
+                        Some(signals) => signals.wait(&fds, timeout),
+                        // without SIGCHLD, children without a pidfd are polled
+                        None => {
+                            let polled = children.iter().any(|child| {
+                                matches!(child.running, Some(Running::Process(_, None)))
+                            });
+                            let timeout = match timeout {
+                                Some(timeout) if polled => {
+                                    Some(timeout.min(SHUTDOWN_POLL_INTERVAL))
                                 }
-                            }
-                            None => {
-                                debug!("pid={ret} not in process map, this shouldn't happen")
-                            }
+                                None if polled => Some(SHUTDOWN_POLL_INTERVAL),
+                                timeout => timeout,
+                            };
+                            signal::poll(fds.iter().map(AsRawFd::as_raw_fd), timeout);
                         }
                     }
+                    continue;
                 }
                 Err(err) => {
                     debug!("waitpid err={err}, stopping process group");
-                    Self::terminate(&mut processes);
-                    break;
+                    Self::shutdown(children, notifier);
+                    return Err(SupertreeError::Waitpid(self.path.clone(), err));
                 }
+            };
+            // a child killed by a signal is an abnormal exit, and is
+            // restarted under its restart policy like any other
+            let child = &mut children[index];
+            if exit_status.is_abnormal() {
+                error!("child path={} failed with {exit_status}", child.path);
+            } else {
+                debug!("child path={} stopped with {exit_status}", child.path);
             }
-        }
+            child.exit_status = Some(exit_status);
+            let pid = child.last_pid as u32;
+            notifier.exited(&child.path, pid, &ChildExit::Process(exit_status));
+            if self.watcher == Some(index) && exit_status.code() == Some(EXIT_INTENSITY_EXCEEDED) {
+                debug!("watcher exceeded restart intensity, stopping process group");
+                Self::shutdown(children, notifier);
+                break EXIT_INTENSITY_EXCEEDED;
+            }
+            if let BackoffResult::RetryAfterDelay(delay) = child.backoff.maybe_delay(exit_status) {
+                if !budget.record() {
+                    debug!("restart intensity exceeded, stopping process group");
+                    notifier.intensity_exceeded(&self.path);
+                    Self::shutdown(children, notifier);
+                    break EXIT_INTENSITY_EXCEEDED;
+                }
+                // siblings that are still running are stopped in the reverse of
+                // their start order, and restarted along with the child that
+                // exited once the delay has passed
+                let range = strategy.restart_range(child.position, usize::MAX);
+                self.restart(children, Some(index), "", range, delay);
+            } else if exit_status.is_abnormal() {
+                notifier.gave_up(&child.path, pid, &ChildExit::Process(exit_status));
+            }
+        };
+        // the children's last frames are routed once they've all stopped
+        Self::route(
+            &self.path,
+            notifier,
+            &mut errors,
+            self.watcher,
+            children,
+            uplink.as_deref(),
+            &mut buf,
+        );
+        let children = children
+            .iter()
+            .map(|child| (child.path.clone(), child.exit_status))
+            .collect();
+        Ok(ExitSummary::new(code, children, errors))
     }
 }
@@ -0,0 +1,208 @@
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::Duration;
+
+use crate::syscall::syscall;
+
+const SHUTDOWN_SIGNALS: [libc::c_int; 2] = [libc::SIGTERM, libc::SIGINT];
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The read and write ends of the self-pipe the handlers write to, so that a
+/// signal arriving at any point wakes up `ShutdownSignals::wait()`.
+static WAKE_READ: AtomicI32 = AtomicI32::new(-1);
+static WAKE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_shutdown(signal: libc::c_int) {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+    wake(signal);
+}
+
+extern "C" fn wake(_signal: libc::c_int) {
+    let fd = WAKE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // the pipe is non-blocking, so this can't block if it's already full,
+        // in which case there's already a wake-up pending
+        unsafe {
+            libc::write(fd, [0u8].as_ptr().cast(), 1);
+        }
+    }
+}
+
+/// Turns SIGTERM and SIGINT into a shutdown request for as long as it's held,
+/// restoring the previous handlers when dropped. The handlers are installed
+/// without `SA_RESTART`, so that blocking calls such as `waitpid()` are
+/// interrupted when a shutdown is requested. SIGCHLD is handled too, so that
+/// [`wait()`](Self::wait) returns as soon as a child stops. Neither applies to
+/// the handlers installed for workers.
+pub struct ShutdownSignals {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl ShutdownSignals {
+    pub fn install() -> io::Result<Self> {
+        Self::install_handlers(true)
+    }
+
+    /// Turns SIGTERM and SIGINT into a shutdown request in a process running
+    /// workers, which leaves SIGCHLD to the workers, and restarts the calls
+    /// the signals interrupt, since those are the workers' own.
+    ///
+    /// The watcher uses this rather than tokio's signals, since a forked
+    /// process shares tokio's wake-up socket with the process it was forked
+    /// from once that process has started a runtime, and either of them could
+    /// take the other's wake-up.
+    pub fn install_for_workers() -> io::Result<Self> {
+        Self::install_handlers(false)
+    }
+
+    fn install_handlers(supervising: bool) -> io::Result<Self> {
+        SHUTDOWN_REQUESTED.store(false, Ordering::SeqCst);
+        let fds = wake_pipe()?;
+        WAKE_READ.store(fds[0], Ordering::SeqCst);
+        WAKE_WRITE.store(fds[1], Ordering::SeqCst);
+
+        let mut previous = vec![];
+        let handlers = SHUTDOWN_SIGNALS
+            .into_iter()
+            .map(|signal| (signal, handle_shutdown as extern "C" fn(libc::c_int)))
+            .chain(supervising.then_some((libc::SIGCHLD, wake as extern "C" fn(libc::c_int))));
+        let flags = match supervising {
+            true => libc::SA_NOCLDSTOP,
+            false => libc::SA_RESTART,
+        };
+        for (signal, handler) in handlers {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler as usize;
+                action.sa_flags = flags;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old: libc::sigaction = std::mem::zeroed();
+                syscall(libc::sigaction(signal, &action, &mut old))?;
+                previous.push((signal, old));
+            }
+        }
+        Ok(Self { previous })
+    }
+
+    /// Returns true if a shutdown has been requested.
+    pub fn requested(&self) -> bool {
+        SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until one of the fds, such as a child's pidfd, becomes readable,
+    /// a child stops, a shutdown is requested, or the timeout elapses. Without
+    /// a timeout, blocks until one of those happens.
+    pub fn wait(&self, fds: &[BorrowedFd], timeout: Option<Duration>) {
+        poll(
+            std::iter::once(self.wake_fd().as_raw_fd()).chain(fds.iter().map(|fd| fd.as_raw_fd())),
+            timeout,
+        );
+        self.drain();
+    }
+
+    /// Returns the read end of the self-pipe, which becomes readable once a
+    /// signal arrives, for waiting on it in a runtime.
+    pub fn wake_fd(&self) -> BorrowedFd<'_> {
+        // the pipe stays open for as long as the handlers are installed
+        unsafe { BorrowedFd::borrow_raw(WAKE_READ.load(Ordering::SeqCst)) }
+    }
+
+    /// Reads the pending wake-ups from the self-pipe.
+    pub fn drain(&self) {
+        let fd = WAKE_READ.load(Ordering::SeqCst);
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+    }
+}
+
+/// Blocks until one of the fds becomes readable, the thread is interrupted by
+/// a signal, or the timeout elapses. Without a timeout, blocks until one of the
+/// others happens.
+pub fn poll(fds: impl Iterator<Item = RawFd>, timeout: Option<Duration>) {
+    let timeout = match timeout {
+        // rounded up, so that a deadline isn't woken up for early
+        Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+        None => -1,
+    };
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // being interrupted by a signal is as good as reading its wake-up
+    unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+}
+
+impl Drop for ShutdownSignals {
+    fn drop(&mut self) {
+        for (signal, old) in self.previous.iter() {
+            unsafe {
+                libc::sigaction(*signal, old, std::ptr::null_mut());
+            }
+        }
+        close_pipe();
+    }
+}
+
+/// Creates the self-pipe, with both ends non-blocking and closed on exec.
+#[cfg(target_os = "linux")]
+fn wake_pipe() -> io::Result<[RawFd; 2]> {
+    let mut fds = [0; 2];
+    unsafe {
+        syscall(libc::pipe2(
+            fds.as_mut_ptr(),
+            libc::O_NONBLOCK | libc::O_CLOEXEC,
+        ))?
+    };
+    Ok(fds)
+}
+
+/// Creates the self-pipe on platforms without `pipe2()`, such as macOS,
+/// setting the flags on both ends once it's created.
+#[cfg(not(target_os = "linux"))]
+fn wake_pipe() -> io::Result<[RawFd; 2]> {
+    let mut fds = [0; 2];
+    unsafe { syscall(libc::pipe(fds.as_mut_ptr()))? };
+    let result: io::Result<()> = fds.iter().try_for_each(|&fd| unsafe {
+        let flags = syscall(libc::fcntl(fd, libc::F_GETFL))?;
+        syscall(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+        syscall(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+        Ok(())
+    });
+    if let Err(err) = result {
+        for fd in fds {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+        return Err(err);
+    }
+    Ok(fds)
+}
+
+fn close_pipe() {
+    for fd in [&WAKE_READ, &WAKE_WRITE] {
+        let fd = fd.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Restores the default handlers in a freshly forked child, which inherits
+/// its parent's handlers, self-pipe and any pending shutdown request.
+pub fn reset() {
+    SHUTDOWN_REQUESTED.store(false, Ordering::SeqCst);
+    for signal in SHUTDOWN_SIGNALS.into_iter().chain([libc::SIGCHLD]) {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    close_pipe();
+}
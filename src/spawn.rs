@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use libc::pid_t;
+
+use crate::ipc;
+use crate::process::set_title;
+
+/// The environment variable naming the node of the tree that a re-executed
+/// process runs, such as `root/db` or `root/db/watcher`.
+const NODE_VAR: &str = "SUPERTREES_NODE";
+
+/// The environment variable holding the fd of the link to the parent process
+/// group of a re-executed process.
+const UPLINK_VAR: &str = "SUPERTREES_UPLINK";
+
+/// The environment variable holding the pid of the process the tree was
+/// started from, which the control sockets of the tree's handles are named
+/// after.
+const ROOT_PID_VAR: &str = "SUPERTREES_ROOT_PID";
+
+/// The environment variable holding the random key of the tree, which the
+/// control sockets of the tree's handles are named after too.
+const KEY_VAR: &str = "SUPERTREES_KEY";
+
+/// The environment variable holding the control sockets a re-executed process
+/// inherits, as comma separated `name=fd` pairs.
+const LISTENERS_VAR: &str = "SUPERTREES_LISTENERS";
+
+/// Represents how a supervisor starts its children, which are the child
+/// supervisors and the watcher running its workers.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Backend {
+    /// Fork the supervisor's process, so that the child runs on a copy of its
+    /// memory. Only safe while the process runs a single thread, see
+    /// [`ThreadPolicy`](crate::ThreadPolicy).
+    #[default]
+    Fork,
+    /// Execute the program again, from `/proc/self/exe` with the same
+    /// arguments, so that each child starts with a fresh address space and
+    /// runs a clean tokio runtime. The child rebuilds the tree and then runs
+    /// its own node of it instead of the root, once
+    /// [`Supertree::start()`](crate::Supertree::start) is called.
+    ///
+    /// The program must build the same tree each time it's run, and shouldn't
+    /// do anything before starting the tree that it wouldn't want repeated in
+    /// each of the tree's processes. Since nothing is inherited from a
+    /// multithreaded parent, it's safe to start the tree from a process that
+    /// runs other threads.
+    ///
+    /// The re-executed processes are told which node to run through
+    /// `SUPERTREES_*` environment variables, which the programs their workers
+    /// run inherit. A worker running a program that starts a tree of its own
+    /// should remove them from its environment, such as with
+    /// [`Command::env_remove()`](std::process::Command::env_remove).
+    ReExec,
+    /// Run each child in a thread of the supervisor's own process, so that
+    /// the supervision semantics are kept without isolating the children,
+    /// for tests, programs that can't fork, or programs that already run on
+    /// tokio.
+    ///
+    /// A child is asked to stop by its supervisor rather than sent SIGTERM,
+    /// and since a thread can't be killed, it's waited for however long it
+    /// takes to stop, whatever its shutdown policy. A panic only takes down
+    /// the child it happened in, but a child that aborts takes down the whole
+    /// process. A child supervisor with its own backend can still start its
+    /// children in processes.
+    Thread,
+}
+
+/// Returns true in a process that was re-executed to run a node of the tree.
+pub(crate) fn is_node() -> bool {
+    static IS_NODE: OnceLock<bool> = OnceLock::new();
+    *IS_NODE.get_or_init(|| std::env::var_os(NODE_VAR).is_some())
+}
+
+/// Returns the pid of the process the tree was started from, which is the
+/// current process unless it was re-executed to run a node of the tree.
+pub(crate) fn root_pid() -> u32 {
+    static ROOT_PID: OnceLock<u32> = OnceLock::new();
+    *ROOT_PID.get_or_init(|| {
+        std::env::var(ROOT_PID_VAR)
+            .ok()
+            .and_then(|pid| pid.parse().ok())
+            .unwrap_or_else(std::process::id)
+    })
+}
+
+/// Returns a random key for the tree, which is picked by the process the tree
+/// was started from and inherited by re-executed processes, so that other
+/// users can't guess the names of the tree's control sockets.
+pub(crate) fn tree_key() -> &'static str {
+    static KEY: OnceLock<String> = OnceLock::new();
+    KEY.get_or_init(|| {
+        std::env::var(KEY_VAR).unwrap_or_else(|_| {
+            // the hasher's keys are seeded from the OS's random source
+            let random = || RandomState::new().build_hasher().finish();
+            format!("{:016x}{:016x}", random(), random())
+        })
+    })
+}
+
+/// Returns the fds inherited by a re-executed process, by name.
+fn inherited_listeners() -> &'static Mutex<HashMap<String, RawFd>> {
+    static LISTENERS: OnceLock<Mutex<HashMap<String, RawFd>>> = OnceLock::new();
+    LISTENERS.get_or_init(|| {
+        let listeners = std::env::var(LISTENERS_VAR).unwrap_or_default();
+        let listeners = listeners
+            .split(',')
+            .filter_map(|listener| listener.split_once('='))
+            .filter_map(|(name, fd)| Some((name.to_string(), fd.parse().ok()?)))
+            .collect();
+        Mutex::new(listeners)
+    })
+}
+
+/// Takes over the fd, which was inherited across exec, so that it isn't
+/// inherited again by programs the process runs.
+fn adopt(fd: RawFd) {
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+}
+
+/// Returns the control socket with the name that a re-executed process
+/// inherited, in place of binding a new one, since the process the tree was
+/// started from has already bound it.
+pub(crate) fn inherited_listener(name: &str) -> Option<UnixListener> {
+    let fd = inherited_listeners()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .remove(name)?;
+    adopt(fd);
+    Some(unsafe { UnixListener::from_raw_fd(fd) })
+}
+
+/// Returns the path of the node of the tree that the current process was
+/// re-executed to run, after linking it to its parent process group. Returns
+/// `None` in the process the tree is started from, and on later calls.
+///
+/// The variables are left in the environment, since changing it while other
+/// threads may be reading it, such as those of a runtime the program started,
+/// is undefined behaviour.
+pub(crate) fn take_node() -> Option<String> {
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+    let node = std::env::var(NODE_VAR).ok()?;
+    let uplink = std::env::var(UPLINK_VAR)
+        .ok()
+        .and_then(|fd| fd.parse().ok());
+    if let Some(fd) = uplink {
+        adopt(fd);
+        ipc::set_uplink(unsafe { UnixDatagram::from_raw_fd(fd) });
+    }
+    set_title(&node);
+    Some(node)
+}
+
+/// Starts a process running the node of the tree with the path, by executing
+/// the program again with the end of the link to it and the control sockets
+/// of the handles in its subtree.
+pub(crate) fn reexec(
+    path: &str,
+    uplink: &UnixDatagram,
+    listeners: &[(String, RawFd)],
+) -> io::Result<pid_t> {
+    let mut args = std::env::args_os();
+    #[cfg(target_os = "linux")]
+    let program = std::path::PathBuf::from("/proc/self/exe");
+    #[cfg(not(target_os = "linux"))]
+    let program = std::env::current_exe()?;
+    let mut command = Command::new(program);
+    if let Some(arg0) = args.next() {
+        command.arg0(arg0);
+    }
+    let inherited: Vec<RawFd> = listeners
+        .iter()
+        .map(|(_, fd)| *fd)
+        .chain([uplink.as_raw_fd()])
+        .collect();
+    command
+        .args(args)
+        .env(NODE_VAR, path)
+        .env(UPLINK_VAR, uplink.as_raw_fd().to_string())
+        .env(ROOT_PID_VAR, root_pid().to_string())
+        .env(KEY_VAR, tree_key())
+        .env(
+            LISTENERS_VAR,
+            listeners
+                .iter()
+                .map(|(name, fd)| format!("{name}={fd}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    // the fds are created close-on-exec, so they're only inherited by the
+    // new process, between its fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    Ok(child.id() as pid_t)
+}
@@ -0,0 +1,31 @@
+/// Represents the restart strategy of a supervisor, which determines which
+/// children are restarted when one of them stops.
+///
+/// Children are ordered by the order in which they were added with
+/// `add_worker()` and `add_supervisor()`, whether they're workers or
+/// supervisors, and the strategy restarts workers and supervisors alike.
+/// Workers started at runtime from templates come after the children the
+/// supervisor was built with, in the order they were started.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Strategy {
+    /// Restart only the child that stopped.
+    #[default]
+    OneForOne,
+    /// Restart every sibling when one child stops.
+    OneForAll,
+    /// Restart the child that stopped, along with every sibling that was added
+    /// after it.
+    RestForOne,
+}
+
+impl Strategy {
+    /// Returns the positions of the children to restart when the child at
+    /// `index` stops, out of `len` children.
+    pub(crate) fn restart_range(&self, index: usize, len: usize) -> std::ops::Range<usize> {
+        match self {
+            Strategy::OneForOne => index..index + 1,
+            Strategy::OneForAll => 0..len,
+            Strategy::RestForOne => index..len,
+        }
+    }
+}
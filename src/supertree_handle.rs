@@ -0,0 +1,75 @@
+use std::os::unix::net::UnixDatagram;
+use std::panic;
+use std::thread::JoinHandle;
+
+use log::debug;
+use tokio::sync::oneshot;
+
+use crate::error::SupertreeError;
+use crate::process::exit_summary::ExitSummary;
+
+/// A handle to a tree spawned on a tokio runtime with
+/// [`Supertree::spawn()`](crate::Supertree::spawn), for shutting it down and
+/// waiting for it to stop. Dropping the handle leaves the tree running.
+///
+/// The root supervisor runs on a dedicated OS thread rather than as a task on
+/// the runtime, since its supervision loop blocks while it waits for its
+/// children, and dropping the handle detaches that thread.
+#[derive(Debug)]
+pub struct SupertreeHandle {
+    /// The socket the root supervisor is asked to stop on, which isn't there
+    /// if the tree couldn't be started.
+    stop: Option<UnixDatagram>,
+    result: oneshot::Receiver<Result<ExitSummary, SupertreeError>>,
+    /// The thread running the root supervisor's process group.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SupertreeHandle {
+    pub(crate) fn new(
+        stop: Option<UnixDatagram>,
+        result: oneshot::Receiver<Result<ExitSummary, SupertreeError>>,
+        thread: Option<JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            stop,
+            result,
+            thread,
+        }
+    }
+
+    /// Asks the tree to shut down, stopping the root supervisor's children in
+    /// the reverse of their start order according to their shutdown
+    /// policies, and waits for it to stop, like [`wait()`](Self::wait).
+    pub async fn shutdown(self) -> Result<ExitSummary, SupertreeError> {
+        if let Some(stop) = &self.stop {
+            // a tree that has already stopped isn't reading its socket
+            if let Err(err) = stop.send(&[0]) {
+                debug!("couldn't ask tree to shut down err={err}");
+            }
+        }
+        self.wait().await
+    }
+
+    /// Waits for the tree to stop, returning how the root supervisor's
+    /// children stopped, or an error if the root supervisor couldn't run
+    /// them, like [`Supertree::start()`](crate::Supertree::start).
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of the root supervisor, if it panicked.
+    pub async fn wait(self) -> Result<ExitSummary, SupertreeError> {
+        match self.result.await {
+            Ok(result) => result,
+            // the result is only dropped unsent when the thread panicked, and
+            // so the thread has finished or is about to
+            Err(_) => {
+                let thread = self.thread.expect("a tree without a result was started");
+                match thread.join() {
+                    Ok(()) => unreachable!("the thread sends its result before finishing"),
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }
+        }
+    }
+}
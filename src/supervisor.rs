@@ -1,102 +1,514 @@
 use std::fmt::{Debug, Display};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::sync::Arc;
 
 use libc::pid_t;
+use tokio::runtime::Handle;
 
-use crate::process::process_group::ProcessGroup;
+use crate::dynamic_supervisor::DynamicSupervisor;
+use crate::error::SupertreeError;
+use crate::event::{EventSink, Notifier, SupervisionEvents};
+use crate::handle::SupervisorHandle;
+use crate::intensity::RestartIntensity;
+use crate::observer::SupervisorObserver;
 use crate::process::Process;
+use crate::process::exit_summary::ExitSummary;
+use crate::process::process_group::ProcessGroup;
+use crate::spawn::Backend;
 use crate::task::Task;
+use crate::worker::Worker;
 use crate::worker::backoff_policy::BackoffPolicy;
 use crate::worker::restartable::{RestartPolicy, Restartable};
-use crate::worker::watcher::Watcher;
-use crate::worker::Worker;
+use crate::worker::shutdown_policy::ShutdownPolicy;
+use crate::worker::template::Templates;
+use crate::worker::watcher::{WATCHER_NAME, Watcher};
+use crate::{Strategy, ipc};
 
 /// Represents a supervisor that manages a collection of supervisors and tasks.
 pub struct Supervisor {
     root_pid: pid_t,
+    path: String,
     tasks: Vec<Task>,
     backoff_policy: BackoffPolicy,
     restart_policy: RestartPolicy,
+    strategy: Strategy,
+    intensity: Option<RestartIntensity>,
+    shutdown_policy: ShutdownPolicy,
+    templates: Templates,
+    /// The handle's control socket, which isn't served by a process
+    /// re-executed to run another node of the tree, along with the handle.
+    control: Option<(Option<UnixListener>, SupervisorHandle)>,
+    max_children: Option<usize>,
+    remove_stopped: bool,
+    observer: Option<Arc<dyn SupervisorObserver>>,
+    events: EventSink,
+    /// How the Supervisor starts its children, which is inherited from its
+    /// own supervisor unless it's set.
+    backend: Option<Backend>,
+    /// Whether the Supervisor runs in a thread of its own supervisor's
+    /// process.
+    in_thread: bool,
+    /// The runtime the Supervisor's workers run on, in the process of a tree
+    /// spawned on a runtime, which is passed down to the children that run in
+    /// threads of the same process.
+    runtime: Option<Handle>,
+    /// The process group running the Supervisor's children, which is kept
+    /// once it's built so that a Supervisor running in a thread can be run
+    /// again.
+    group: Option<ProcessGroup>,
 }
 
 impl Debug for Supervisor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Supervisor")
             .field("root_pid", &self.root_pid)
+            .field("path", &self.path)
+            .field("strategy", &self.strategy)
+            .field("intensity", &self.intensity)
+            .field("templates", &self.templates)
             .field("tasks", &self.tasks)
             .finish()
     }
 }
 
 impl Supervisor {
-    pub(crate) fn new(root_pid: pid_t) -> Self {
+    pub(crate) fn new(root_pid: pid_t, path: String) -> Self {
         Self {
             root_pid,
+            path,
             tasks: vec![],
             backoff_policy: BackoffPolicy::default(),
             restart_policy: RestartPolicy::default(),
+            strategy: Strategy::default(),
+            intensity: None,
+            shutdown_policy: ShutdownPolicy::Infinity,
+            templates: Templates::default(),
+            control: None,
+            max_children: None,
+            remove_stopped: false,
+            observer: None,
+            events: EventSink::None,
+            backend: None,
+            in_thread: false,
+            runtime: None,
+            group: None,
         }
     }
 
+    /// Returns the path of the Supervisor in the tree, which is made up of the
+    /// names of its ancestors and its own name, such as `root/db/pool`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
     /// Sets the backoff policy for the Supervisor.
     pub fn with_backoff_policy(mut self, backoff_policy: BackoffPolicy) -> Self {
         self.backoff_policy = backoff_policy;
         self
     }
 
-    /// Sets the restart policy for the Supervisor.
+    /// Sets the restart policy for the Supervisor. Defaults to
+    /// [`RestartPolicy::Always`], like workers, so a supervisor whose children
+    /// have all finished is restarted too. Use [`RestartPolicy::Transient`] to
+    /// only restart it when it fails, such as after it exceeded its restart
+    /// intensity.
     pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
         self.restart_policy = restart_policy;
         self
     }
 
-    pub(crate) fn run(&mut self) {
+    /// Sets the restart strategy for the Supervisor, which determines which of
+    /// its children are restarted when one of them stops.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the restart intensity for the Supervisor. When its children are
+    /// restarted more often than the intensity allows, the Supervisor stops
+    /// all of its children and exits, and its own supervisor decides whether
+    /// to restart it. By default, there's no limit.
+    pub fn with_restart_intensity(mut self, intensity: RestartIntensity) -> Self {
+        self.intensity = Some(intensity);
+        self
+    }
+
+    /// Sets the shutdown policy for the Supervisor, which determines how long
+    /// its own supervisor waits for it to stop its children before killing
+    /// it. Defaults to [`ShutdownPolicy::Infinity`].
+    pub fn with_shutdown_policy(mut self, shutdown_policy: ShutdownPolicy) -> Self {
+        self.shutdown_policy = shutdown_policy;
+        self
+    }
+
+    /// Sets the observer notified when the Supervisor's children start, stop
+    /// and are restarted. Child supervisors without their own observer
+    /// inherit it.
+    pub fn with_observer(mut self, observer: impl SupervisorObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Sets how the Supervisor starts its children, which child supervisors
+    /// without their own backend inherit. By default, a supervisor inherits
+    /// its own supervisor's backend, and the root supervisor uses
+    /// [`Backend::Fork`], or [`Backend::Thread`] in a tree started with
+    /// [`Supertree::spawn()`](crate::Supertree::spawn).
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Returns how the Supervisor starts its children.
+    fn backend(&self) -> Backend {
+        self.backend.unwrap_or_default()
+    }
+
+    /// Returns true if the Supervisor forks the processes for its children,
+    /// or one of the supervisors running in threads of its process does,
+    /// given the backend it would inherit.
+    pub(crate) fn forks(&self, inherited: Backend) -> bool {
+        match self.backend.unwrap_or(inherited) {
+            Backend::Fork => true,
+            Backend::ReExec => false,
+            Backend::Thread => self.tasks.iter().any(|task| match task {
+                Task::Supervisor(_, s) => s.forks(Backend::Thread),
+                Task::Worker(..) => false,
+            }),
+        }
+    }
+
+    /// Adds a named template for workers started at runtime with
+    /// [`SupervisorHandle::start_child()`], which builds the worker from the
+    /// ID it's started with.
+    pub fn add_template<W, F>(mut self, name: &str, template: F) -> Self
+    where
+        W: Worker + 'static,
+        F: Fn(&str) -> W + Send + 'static,
+    {
+        self.templates.insert(name, template);
+        self
+    }
+
+    /// Limits how many workers the Supervisor may have, counting those that
+    /// have stopped but haven't been deleted.
+    pub(crate) fn with_max_children(mut self, max_children: usize) -> Self {
+        self.max_children = Some(max_children);
+        self
+    }
+
+    /// Removes workers that are given up on, rather than keeping them until
+    /// they're deleted.
+    pub(crate) fn with_remove_stopped(mut self) -> Self {
+        self.remove_stopped = true;
+        self
+    }
+
+    /// Returns a handle for managing the Supervisor's children at runtime. The
+    /// handle's socket is bound on the first call, and later calls return a
+    /// clone of the same handle.
+    pub fn handle(&mut self) -> io::Result<SupervisorHandle> {
+        if self.control.is_none() {
+            self.control = Some(SupervisorHandle::bind()?);
+        }
+        Ok(self
+            .control
+            .as_ref()
+            .map(|(_, handle)| handle.clone())
+            .unwrap())
+    }
+
+    /// Subscribes to the events of the Supervisor's children and of its
+    /// descendants, which are forwarded to the Supervisor's process.
+    pub(crate) fn subscribe(&mut self) -> io::Result<SupervisionEvents> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn run(&mut self) -> Result<ExitSummary, SupertreeError> {
+        let mut group = match self.group.take() {
+            Some(group) => group,
+            None => self.process_group(),
+        };
+        let result = group.run();
+        self.group = Some(group);
+        result
+    }
+
+    /// Runs the Supervisor's children until they've all stopped, or until a
+    /// datagram is received on the stop socket, sharing the runtime with the
+    /// children that run in threads. Unless it has its own backend, the
+    /// Supervisor runs its children in threads, and only those supervisors
+    /// with a backend that isolates their children start processes.
+    pub(crate) fn run_on(
+        &mut self,
+        runtime: Handle,
+        stop: UnixDatagram,
+    ) -> Result<ExitSummary, SupertreeError> {
+        self.backend.get_or_insert(Backend::Thread);
+        self.runtime = Some(runtime);
+        // the program's signals are its own, and it stops the tree through
+        // the stop socket instead
+        self.in_thread = true;
+        self.process_group().with_stop(stop).run()
+    }
+
+    /// Runs the node of the tree with the path, which is the Supervisor, the
+    /// watcher running its workers or a node within one of its child
+    /// supervisors, in a process that was re-executed to run it. Returns
+    /// `None` if there's no such node.
+    pub(crate) fn run_node(&mut self, node: &str) -> Option<Result<i32, SupertreeError>> {
+        if node == self.path {
+            return Some(self.start());
+        }
+        if node == format!("{}/{WATCHER_NAME}", self.path) {
+            return self
+                .process_group()
+                .into_watcher()
+                .map(|mut watcher| watcher.start());
+        }
+        let index = self.tasks.iter().position(|task| match task {
+            Task::Supervisor(_, s) => ipc::is_within(node, &s.path),
+            Task::Worker(..) => false,
+        })?;
+        let Task::Supervisor(_, mut supervisor) = self.tasks.remove(index) else {
+            unreachable!("the node is within a child supervisor");
+        };
+        self.adopt(&mut supervisor);
+        supervisor.run_node(node)
+    }
+
+    /// Passes on what a child supervisor inherits from the Supervisor before
+    /// it's started.
+    fn adopt(&self, child: &mut Supervisor) {
+        if child.observer.is_none() {
+            child.observer = self.observer.clone();
+        }
+        child.events = self.events.forwarded();
+        if child.backend.is_none() {
+            child.backend = self.backend;
+        }
+        child.in_thread = self.backend() == Backend::Thread;
+        child.runtime = self.thread_runtime();
+    }
+
+    /// Returns the runtime shared with the children that run in threads of
+    /// the Supervisor's process, if it has one.
+    fn thread_runtime(&self) -> Option<Handle> {
+        self.runtime
+            .clone()
+            .filter(|_| self.backend() == Backend::Thread)
+    }
+
+    /// Builds the process group running the Supervisor's children, with the
+    /// watcher running its workers.
+    fn process_group(&mut self) -> ProcessGroup {
         let tasks = std::mem::take(&mut self.tasks);
+        let members = tasks.len();
+        // the watcher is started in place of the first worker among the child
+        // processes, after the supervisors added before it
+        let first_worker = tasks.iter().position(|t| matches!(t, Task::Worker(..)));
+        let watcher_index = first_worker.unwrap_or(0);
+        let observer = self.observer.clone().unwrap_or_else(|| Arc::new(()));
+        // the children keep their positions in the order they were added, so
+        // that the strategy restarts workers and supervisors alike
         let (workers, supervisors): (Vec<_>, Vec<_>) = tasks
             .into_iter()
-            .partition(|w| matches!(w, Task::Worker(_)));
-        let worker_watcher = Watcher::new(
+            .enumerate()
+            .partition(|(_, w)| matches!(w, Task::Worker(..)));
+        let worker_positions = workers.iter().map(|(position, _)| *position).collect();
+        let mut positions: Vec<usize> = supervisors.iter().map(|(position, _)| *position).collect();
+        positions.insert(watcher_index, first_worker.unwrap_or(members));
+        let mut worker_watcher = Watcher::new(
+            self.path.clone(),
             workers
                 .into_iter()
-                .filter_map(|w| match w {
-                    Task::Worker(w) => Some(w),
+                .filter_map(|(_, w)| match w {
+                    Task::Worker(name, w) => Some((name, w)),
                     _ => None,
                 })
                 .collect(),
-        );
+            self.strategy,
+            self.intensity,
+        )
+        .with_notifier(Notifier::new(observer.clone(), self.events.forwarded()))
+        .with_positions(worker_positions, members)
+        .with_supervisors(
+            supervisors
+                .iter()
+                .map(|(position, s)| (s.name().to_string(), *position))
+                .collect(),
+        )
+        .with_in_thread(self.backend() == Backend::Thread)
+        .with_runtime(self.thread_runtime());
+        if let Some((Some(listener), handle)) = self.control.take() {
+            worker_watcher = worker_watcher
+                .with_control(handle.name(), listener, std::mem::take(&mut self.templates))
+                .with_max_children(self.max_children)
+                .with_remove_stopped(self.remove_stopped);
+        }
 
-        let mut pg = ProcessGroup::new();
-        pg.add_process(Box::new(worker_watcher));
-        for supervisor in supervisors.into_iter() {
-            if let Task::Supervisor(s) = supervisor {
-                pg.add_process(Box::new(s))
+        let mut pg = ProcessGroup::new(self.path.clone(), self.strategy, self.intensity)
+            .with_positions(positions)
+            .with_backend(self.backend())
+            .with_in_thread(self.in_thread);
+        for (_, supervisor) in supervisors.into_iter() {
+            if let Task::Supervisor(_, mut s) = supervisor {
+                self.adopt(&mut s);
+                pg.add_process(s.path.clone(), s)
             }
         }
+        // the processes started by the supervisor send their events to it
+        let events = std::mem::take(&mut self.events);
+        pg = pg.with_notifier(Notifier::new(observer, events));
+        // the watcher is named after the supervisor whose workers it runs
+        pg.insert_watcher(
+            watcher_index,
+            format!("{}/{WATCHER_NAME}", self.path),
+            Box::new(worker_watcher),
+        );
+        pg
+    }
+
+    /// Returns the path of the child with the name, after checking that the
+    /// name is valid and unique among the Supervisor's children.
+    fn child_path(&self, name: &str) -> String {
+        assert!(
+            !name.is_empty() && !name.contains(['/', '\t', '\n']),
+            "invalid child name {name:?}"
+        );
+        assert!(
+            name != WATCHER_NAME,
+            "child name {name:?} is reserved for the supervisor's watcher"
+        );
+        assert!(
+            self.tasks.iter().all(|task| task.name() != name),
+            "duplicate child name {name:?} in supervisor {}",
+            self.path
+        );
+        format!("{}/{name}", self.path)
+    }
 
-        pg.run();
+    /// Returns a name with the prefix for a child added without one, numbered
+    /// after the child's position among the supervisor's children, or after
+    /// the next position whose name isn't already taken by a named child.
+    fn unused_name(&self, prefix: &str) -> String {
+        (self.tasks.len()..)
+            .map(|n| format!("{prefix}-{n}"))
+            .find(|name| self.tasks.iter().all(|task| task.name() != name))
+            .expect("there's always an unused name")
     }
 
-    /// Adds a worker to the supervisor.
-    pub fn add_worker(mut self, worker: impl Worker + 'static) -> Self {
-        let _id = self.tasks.len();
-        self.tasks.push(Task::Worker(Box::new(worker)));
+    /// Adds a worker to the supervisor, named after its position among the
+    /// supervisor's children, such as `worker-2`. If a named child already
+    /// has that name, the worker is numbered after the next free position
+    /// instead.
+    ///
+    /// # Panics
+    ///
+    /// Never panics itself, but the name it picks is taken from then on, so
+    /// adding a named child with the same name afterwards panics in
+    /// [`add_named_worker()`](Self::add_named_worker) and the like.
+    pub fn add_worker(self, worker: impl Worker + 'static) -> Self {
+        let name = self.unused_name("worker");
+        self.add_named_worker(&name, worker)
+    }
+
+    /// Adds a worker with the name to the supervisor, which identifies it in
+    /// logs and in the supervisor's handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is empty, contains a `/`, tab or newline, is
+    /// `watcher`, which is reserved for the process running the supervisor's
+    /// workers, or is already used by another of the supervisor's children,
+    /// including the names given to children added without one.
+    pub fn add_named_worker(mut self, name: &str, worker: impl Worker + 'static) -> Self {
+        self.child_path(name);
+        self.tasks
+            .push(Task::Worker(name.to_string(), Box::new(worker)));
+        self
+    }
+
+    /// Adds a new child dynamic supervisor to the current one, calling the
+    /// closure provided with the new dynamic supervisor. It's named after its
+    /// position among the supervisor's children, such as `supervisor-2`, or
+    /// after the next free position if that name is taken.
+    pub fn add_dynamic_supervisor<F>(self, f: F) -> Self
+    where
+        F: FnOnce(DynamicSupervisor) -> DynamicSupervisor,
+    {
+        let name = self.unused_name("supervisor");
+        self.add_named_dynamic_supervisor(&name, f)
+    }
+
+    /// Adds a new child dynamic supervisor with the name to the current one,
+    /// calling the closure provided with the new dynamic supervisor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is invalid or already used, like
+    /// [`add_named_worker()`](Self::add_named_worker).
+    pub fn add_named_dynamic_supervisor<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(DynamicSupervisor) -> DynamicSupervisor,
+    {
+        let path = self.child_path(name);
+        let dynamic = f(DynamicSupervisor::new(Supervisor::new(self.root_pid, path)));
+        self.tasks.push(Task::Supervisor(
+            name.to_string(),
+            Box::new(dynamic.into_supervisor()),
+        ));
         self
     }
 
     /// Adds a new child supervisor to the current one, calling the closure
-    /// provided with the new supervisor.
-    pub fn add_supervisor<F>(mut self, f: F) -> Self
+    /// provided with the new supervisor. It's named after its position among
+    /// the supervisor's children, such as `supervisor-2`, or after the next
+    /// free position if that name is taken.
+    pub fn add_supervisor<F>(self, f: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        let name = self.unused_name("supervisor");
+        self.add_named_supervisor(&name, f)
+    }
+
+    /// Adds a new child supervisor with the name to the current one, calling
+    /// the closure provided with the new supervisor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is invalid or already used, like
+    /// [`add_named_worker()`](Self::add_named_worker).
+    pub fn add_named_supervisor<F>(mut self, name: &str, f: F) -> Self
     where
         F: FnOnce(Self) -> Self,
     {
+        let path = self.child_path(name);
+        let supervisor = f(Supervisor::new(self.root_pid, path));
         self.tasks
-            .push(Task::Supervisor(f(Supervisor::new(self.root_pid))));
+            .push(Task::Supervisor(name.to_string(), Box::new(supervisor)));
         self
     }
 }
 
 impl Process for Supervisor {
-    fn start(&mut self) {
-        self.run();
+    fn start(&mut self) -> Result<i32, SupertreeError> {
+        self.run().map(|summary| summary.code())
+    }
+
+    fn listeners(&self) -> Vec<(String, RawFd)> {
+        let own = self.control.iter().filter_map(|(listener, handle)| {
+            let listener = listener.as_ref()?;
+            Some((handle.name().to_string(), listener.as_raw_fd()))
+        });
+        let children = self.tasks.iter().flat_map(|task| match task {
+            Task::Supervisor(_, s) => s.listeners(),
+            Task::Worker(..) => vec![],
+        });
+        own.chain(children).collect()
     }
 }
 
@@ -114,4 +526,8 @@ impl Restartable for Supervisor {
     fn restart_policy(&self) -> RestartPolicy {
         self.restart_policy
     }
+
+    fn shutdown_policy(&self) -> ShutdownPolicy {
+        self.shutdown_policy
+    }
 }
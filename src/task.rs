@@ -1,20 +1,30 @@
 use std::fmt::Debug;
 
-use crate::supervisor::Supervisor;
 use crate::Worker;
+use crate::supervisor::Supervisor;
 
+/// A child of a supervisor, along with its name, which is unique among its
+/// siblings.
 pub enum Task {
-    Worker(Box<dyn Worker>),
-    Supervisor(Supervisor),
+    Worker(String, Box<dyn Worker>),
+    Supervisor(String, Box<Supervisor>),
+}
+
+impl Task {
+    pub fn name(&self) -> &str {
+        match self {
+            Task::Worker(name, _) | Task::Supervisor(name, _) => name,
+        }
+    }
 }
 
 impl Debug for Task {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Task::Worker(_worker_task) => {
-                write!(f, "Worker")
+            Task::Worker(name, _worker_task) => {
+                write!(f, "Worker({name})")
             }
-            Task::Supervisor(s) => s.fmt(f),
+            Task::Supervisor(_, s) => s.fmt(f),
         }
     }
 }
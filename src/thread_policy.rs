@@ -0,0 +1,28 @@
+/// Represents what a supervision tree does when it's started from a process
+/// that already runs other threads, such as a tokio runtime or a logger's
+/// background thread.
+///
+/// The tree forks a process for each supervisor, and a forked child only runs
+/// the thread that forked it. A lock held by any other thread at the time of
+/// the fork, such as the allocator's or a logger's, stays locked forever in
+/// the child, which deadlocks the first time it takes the lock.
+///
+/// Threads are counted from `/proc/self/task`, so they aren't detected on
+/// platforms without it. The policy only applies to a tree that forks from the
+/// process it's started from, so not to one started with
+/// [`Backend::ReExec`](crate::Backend::ReExec), whose children don't inherit
+/// the state of the threads, or with
+/// [`Backend::Thread`](crate::Backend::Thread) unless one of its supervisors
+/// forks.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ThreadPolicy {
+    /// Refuse to start the tree, returning
+    /// [`SupertreeError::Multithreaded`](crate::SupertreeError::Multithreaded).
+    #[default]
+    Refuse,
+    /// Start the tree anyway, for threads that are known not to hold locks
+    /// while the tree forks, such as one waiting on a
+    /// [`SupervisorHandle`](crate::SupervisorHandle) or reading the tree's
+    /// events.
+    Allow,
+}
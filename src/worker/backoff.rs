@@ -1,12 +1,14 @@
 use std::ops::{Deref, DerefMut};
 use std::time::{Duration, Instant};
 
-use super::restartable::{RestartPolicy, Restartable};
+use super::restartable::Restartable;
+use crate::ExitStatus;
 
 #[derive(Debug)]
 pub struct Backoff<Inner: ?Sized> {
     inner: Box<Inner>,
     last_action: Option<Instant>,
+    restarts: usize,
 }
 
 pub enum BackoffResult {
@@ -19,17 +21,26 @@ impl<Inner: ?Sized> Backoff<Inner> {
         Self {
             inner,
             last_action: None,
+            restarts: 0,
         }
     }
 
+    /// Returns the process or task, once it's no longer restarted.
     pub fn into_inner(self) -> Box<Inner> {
         self.inner
     }
 }
 
 impl<Inner: Restartable + ?Sized> Backoff<Inner> {
-    pub fn maybe_delay(&mut self) -> BackoffResult {
-        if self.inner.restart_policy() == RestartPolicy::Never {
+    /// Returns the delay after which to restart the process or task, which
+    /// stopped with the exit status, or gives up if its restart policy doesn't
+    /// allow it to be restarted.
+    pub fn maybe_delay(&mut self, exit_status: ExitStatus) -> BackoffResult {
+        if !self
+            .inner
+            .restart_policy()
+            .should_restart(exit_status, self.restarts)
+        {
             return BackoffResult::GiveUp;
         }
         let backoff_policy = self.inner.backoff_policy();
@@ -55,15 +66,9 @@ impl<Inner: Restartable + ?Sized> Backoff<Inner> {
         } else {
             backoff_policy.min_delay()
         };
-        let ret = match self.inner.restart_policy() {
-            RestartPolicy::Always => BackoffResult::RetryAfterDelay(delay),
-            RestartPolicy::Once if self.last_action.is_none() => {
-                BackoffResult::RetryAfterDelay(delay)
-            }
-            _ => BackoffResult::GiveUp,
-        };
         self.last_action = Some(now);
-        ret
+        self.restarts += 1;
+        BackoffResult::RetryAfterDelay(delay)
     }
 }
 
@@ -0,0 +1,109 @@
+use super::link::{LinkError, Links, Monitor};
+use super::mailbox::Mailbox;
+use super::registry::{Registry, RegistryError};
+use super::shutdown::Shutdown;
+use crate::ipc::IpcReceiver;
+use crate::ipc::codec::Codec;
+
+/// The context a worker is started with, which is passed to
+/// [`Worker::init()`](crate::Worker::init) each time the worker is started.
+#[derive(Debug, Clone)]
+pub struct Context {
+    shutdown: Shutdown,
+    id: String,
+    path: String,
+    registry: Registry,
+    inbox: Mailbox<Vec<u8>>,
+    links: Links,
+}
+
+impl Context {
+    pub(crate) fn new(
+        shutdown: Shutdown,
+        id: String,
+        path: String,
+        registry: Registry,
+        inbox: Mailbox<Vec<u8>>,
+        links: Links,
+    ) -> Self {
+        Self {
+            shutdown,
+            id,
+            path,
+            registry,
+            inbox,
+            links,
+        }
+    }
+
+    /// Returns the path of the worker with the ID in the same supervisor.
+    fn sibling_path(&self, id: &str) -> String {
+        let parent = self.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        format!("{parent}/{id}")
+    }
+
+    /// Returns the worker's ID, which is its name, or the ID it was started
+    /// with from a template.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the worker's path in the tree, such as `root/db/pool-3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the registry shared by the workers running alongside this one,
+    /// for looking up values they've registered.
+    pub fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
+
+    /// Registers the value under the name, so that other workers in the same
+    /// supervisor can look it up until this worker stops. Fails if another
+    /// worker has registered the name.
+    pub fn register<T>(&self, name: &str, value: T) -> Result<(), RegistryError>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.registry.register(&self.path, name, value)
+    }
+
+    /// Removes the name, returning true if this worker had registered it.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.registry.unregister(&self.path, name)
+    }
+
+    /// Returns a receiver for the messages sent to the worker's path by
+    /// [`IpcSender`](crate::IpcSender)s elsewhere in the tree, decoded with
+    /// the codec. Messages sent while the worker is restarted are kept until
+    /// they're received.
+    pub fn receiver<M, C: Codec<M>>(&self, codec: C) -> IpcReceiver<M, C> {
+        IpcReceiver::new(self.inbox.clone(), codec)
+    }
+
+    /// Starts watching the running worker with the ID in the same supervisor,
+    /// returning a monitor that delivers a [`Down`](crate::Down) message when
+    /// it stops. Workers in other supervisors can't be monitored.
+    pub fn monitor(&self, id: &str) -> Result<Monitor, LinkError> {
+        self.links.monitor(&self.sibling_path(id), id)
+    }
+
+    /// Links this worker to the running worker with the ID in the same
+    /// supervisor, so that if either of them fails, the other one fails too,
+    /// and is restarted according to its restart policy. The link lasts until
+    /// one of them stops.
+    pub fn link(&self, id: &str) -> Result<(), LinkError> {
+        self.links.link(&self.path, &self.sibling_path(id), id)
+    }
+
+    /// Removes the link to the worker with the ID, if there is one.
+    pub fn unlink(&self, id: &str) {
+        self.links.unlink(&self.path, &self.sibling_path(id));
+    }
+
+    /// Returns the signal that's set when the worker is asked to stop.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+}
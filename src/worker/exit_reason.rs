@@ -0,0 +1,65 @@
+use std::fmt::Display;
+
+use crate::ExitStatus;
+use crate::process::{EXIT_ERROR, EXIT_PANIC, EXIT_SHUTDOWN};
+
+/// A boxed error returned by a worker that failed.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Represents why a worker stopped.
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The worker finished on its own.
+    Normal,
+    /// The worker stopped after it was asked to by its supervisor.
+    Shutdown,
+    /// The worker returned an error.
+    Error(BoxError),
+    /// The worker panicked with the message.
+    Panic(String),
+}
+
+impl ExitReason {
+    /// Returns the reason for a worker that returned `result`, depending on
+    /// whether it had been asked to stop.
+    pub(crate) fn from_result(result: Result<(), BoxError>, shutdown_requested: bool) -> Self {
+        match result {
+            Ok(()) if shutdown_requested => Self::Shutdown,
+            Ok(()) => Self::Normal,
+            Err(err) => Self::Error(err),
+        }
+    }
+
+    /// Returns true if the worker failed, rather than finishing or stopping
+    /// when asked to.
+    pub fn is_abnormal(&self) -> bool {
+        matches!(self, Self::Error(_) | Self::Panic(_))
+    }
+
+    /// Returns the exit code a process stopping for this reason exits with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Normal => 0,
+            Self::Shutdown => EXIT_SHUTDOWN,
+            Self::Error(_) => EXIT_ERROR,
+            Self::Panic(_) => EXIT_PANIC,
+        }
+    }
+
+    /// Returns the exit status of a process stopping for this reason, which
+    /// restart policies are applied to.
+    pub(crate) fn exit_status(&self) -> ExitStatus {
+        ExitStatus::Exited(self.exit_code())
+    }
+}
+
+impl Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => write!(f, "normal"),
+            Self::Shutdown => write!(f, "shutdown"),
+            Self::Error(err) => write!(f, "error: {err}"),
+            Self::Panic(message) => write!(f, "panic: {message}"),
+        }
+    }
+}
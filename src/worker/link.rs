@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::oneshot;
+
+use super::exit_reason::ExitReason;
+
+/// The message a [`Monitor`] delivers when the worker it watches stops, like
+/// Erlang/OTP's `'DOWN'` message.
+#[derive(Debug, Clone)]
+pub struct Down {
+    id: String,
+    reason: Arc<ExitReason>,
+}
+
+impl Down {
+    /// Returns the ID of the worker that stopped.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns why the worker stopped.
+    pub fn reason(&self) -> &ExitReason {
+        &self.reason
+    }
+}
+
+/// Watches a worker in the same supervisor until it stops, which is returned
+/// by [`Context::monitor()`](crate::Context::monitor).
+///
+/// A monitor watches the instance of the worker that was running when it was
+/// created, so it delivers a single [`Down`] message even if the worker is
+/// restarted.
+#[derive(Debug)]
+pub struct Monitor {
+    id: String,
+    down: oneshot::Receiver<Down>,
+}
+
+impl Monitor {
+    /// Returns the ID of the watched worker.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Waits for the watched worker to stop. Returns `None` if the supervisor
+    /// stops first.
+    pub async fn down(self) -> Option<Down> {
+        self.down.await.ok()
+    }
+}
+
+/// Represents an error returned when monitoring or linking to a worker.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LinkError {
+    /// There's no running worker with the ID in the supervisor.
+    NotRunning(String),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotRunning(id) => write!(f, "worker {id:?} isn't running"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+#[derive(Default)]
+struct Inner {
+    /// The running workers, by path, along with how to stop them when a
+    /// worker they're linked to fails.
+    running: HashMap<String, oneshot::Sender<String>>,
+    /// The paths of the workers linked to each worker, in both directions.
+    links: HashMap<String, HashSet<String>>,
+    /// The monitors watching each worker.
+    monitors: HashMap<String, Vec<oneshot::Sender<Down>>>,
+}
+
+/// The monitors and links between the workers of a supervisor, which last as
+/// long as the instances of the workers that made them.
+#[derive(Clone, Default)]
+pub(crate) struct Links {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Debug for Links {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner();
+        f.debug_struct("Links")
+            .field("running", &inner.running.keys())
+            .field("links", &inner.links)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Links {
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records that the worker with the path has started, returning a
+    /// receiver that's sent why it should fail when a worker it's linked to
+    /// fails.
+    pub(crate) fn started(&self, path: &str) -> oneshot::Receiver<String> {
+        let (crash, crashed) = oneshot::channel();
+        self.inner().running.insert(path.to_string(), crash);
+        crashed
+    }
+
+    /// Records that the worker with the path has stopped, delivering its
+    /// monitors' messages and failing the workers linked to it if it failed.
+    pub(crate) fn stopped(&self, path: &str, id: &str, reason: &Arc<ExitReason>) {
+        let mut inner = self.inner();
+        inner.running.remove(path);
+        for monitor in inner.monitors.remove(path).unwrap_or_default() {
+            let _ = monitor.send(Down {
+                id: id.to_string(),
+                reason: reason.clone(),
+            });
+        }
+        for linked in inner.links.remove(path).unwrap_or_default() {
+            if let Some(links) = inner.links.get_mut(&linked) {
+                links.remove(path);
+            }
+            if reason.is_abnormal() {
+                if let Some(crash) = inner.running.remove(&linked) {
+                    let _ = crash.send(format!("linked worker {path} failed with {reason}"));
+                }
+            }
+        }
+    }
+
+    /// Starts monitoring the running worker with the path.
+    pub(crate) fn monitor(&self, path: &str, id: &str) -> Result<Monitor, LinkError> {
+        let mut inner = self.inner();
+        if !inner.running.contains_key(path) {
+            return Err(LinkError::NotRunning(id.to_string()));
+        }
+        let (down_tx, down) = oneshot::channel();
+        inner
+            .monitors
+            .entry(path.to_string())
+            .or_default()
+            .push(down_tx);
+        Ok(Monitor {
+            id: id.to_string(),
+            down,
+        })
+    }
+
+    /// Links the two running workers, so that if either fails, the other one
+    /// fails too.
+    pub(crate) fn link(&self, path: &str, other: &str, id: &str) -> Result<(), LinkError> {
+        let mut inner = self.inner();
+        if !inner.running.contains_key(other) {
+            return Err(LinkError::NotRunning(id.to_string()));
+        }
+        if path != other {
+            for (from, to) in [(path, other), (other, path)] {
+                inner
+                    .links
+                    .entry(from.to_string())
+                    .or_default()
+                    .insert(to.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the link between the two workers, if there is one.
+    pub(crate) fn unlink(&self, path: &str, other: &str) {
+        let mut inner = self.inner();
+        for (from, to) in [(path, other), (other, path)] {
+            if let Some(links) = inner.links.get_mut(from) {
+                links.remove(to);
+            }
+        }
+    }
+}
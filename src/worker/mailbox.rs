@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::debug;
+use tokio::sync::Notify;
+
+/// Represents what happens to a message sent to a [`Mailbox`] that's full,
+/// for example because the worker receiving from it is backing off.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Reject the message, returning it to the sender.
+    #[default]
+    DropNewest,
+    /// Accept the message, dropping the oldest message in the mailbox to make
+    /// room for it.
+    DropOldest,
+}
+
+struct Queue<M> {
+    messages: VecDeque<M>,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+}
+
+struct Shared<M> {
+    queue: Mutex<Queue<M>>,
+    /// Wakes receivers when a message is sent or the last sender is dropped.
+    notify: Notify,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl<M> Shared<M> {
+    fn queue(&self) -> MutexGuard<'_, Queue<M>> {
+        // the queue is left consistent by every operation, so it's still
+        // usable if a worker panicked while holding the lock
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A worker's mailbox, which buffers messages of type `M` sent by other
+/// workers in the same supervisor through a [`MailboxSender`].
+///
+/// The mailbox is created when the tree is built and kept in the worker, so
+/// that it outlives each started instance of the worker: messages sent while
+/// the worker is stopped or backing off are buffered, up to the mailbox's
+/// capacity, and received once it's restarted. What happens to messages sent
+/// to a full mailbox is determined by its [`OverflowPolicy`].
+///
+/// Workers in other supervisors run in other processes, so they can't share a
+/// mailbox.
+pub struct Mailbox<M> {
+    shared: Arc<Shared<M>>,
+}
+
+impl<M> Mailbox<M> {
+    /// Creates a mailbox that buffers up to `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue {
+                    messages: VecDeque::new(),
+                    capacity,
+                    overflow_policy: OverflowPolicy::default(),
+                }),
+                notify: Notify::new(),
+                senders: AtomicUsize::new(0),
+                receivers: AtomicUsize::new(1),
+            }),
+        }
+    }
+
+    /// Sets what happens to messages sent while the mailbox is full. By
+    /// default, they're rejected.
+    pub fn with_overflow_policy(self, overflow_policy: OverflowPolicy) -> Self {
+        self.shared.queue().overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Returns a sender for the mailbox, which can be given to other workers
+    /// when the tree is built, or registered with
+    /// [`Context::register()`](crate::Context::register).
+    pub fn sender(&self) -> MailboxSender<M> {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        MailboxSender {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Receives the next message, waiting for one to be sent. Returns `None`
+    /// once the mailbox is empty and all of its senders have been dropped.
+    pub async fn recv(&self) -> Option<M> {
+        loop {
+            // the notification is enabled before checking the queue, so that
+            // a message sent in between isn't missed
+            let notified = self.shared.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if let Some(message) = self.try_recv() {
+                return Some(message);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            notified.await;
+        }
+    }
+
+    /// Receives the next message if there is one, without waiting.
+    pub fn try_recv(&self) -> Option<M> {
+        self.shared.queue().messages.pop_front()
+    }
+
+    /// Returns the number of buffered messages.
+    pub fn len(&self) -> usize {
+        self.shared.queue().messages.len()
+    }
+
+    /// Returns true if there are no buffered messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<M> Clone for Mailbox<M> {
+    /// Returns another receiver for the same mailbox, such as one to move into
+    /// the future of a started worker. Each message is received once.
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<M> Drop for Mailbox<M> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<M> Debug for Mailbox<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let queue = self.shared.queue();
+        f.debug_struct("Mailbox")
+            .field("len", &queue.messages.len())
+            .field("capacity", &queue.capacity)
+            .field("overflow_policy", &queue.overflow_policy)
+            .finish()
+    }
+}
+
+/// A handle for sending messages to a [`Mailbox`], which can be cloned and
+/// shared between the workers of a supervisor.
+pub struct MailboxSender<M> {
+    shared: Arc<Shared<M>>,
+}
+
+impl<M> MailboxSender<M> {
+    /// Sends the message without waiting, buffering it until it's received.
+    /// Fails if the mailbox is full and rejects new messages, or if the
+    /// mailbox has been dropped.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(SendError::Closed(message));
+        }
+        {
+            let mut queue = self.shared.queue();
+            if queue.messages.len() >= queue.capacity {
+                match queue.overflow_policy {
+                    OverflowPolicy::DropNewest => return Err(SendError::Full(message)),
+                    OverflowPolicy::DropOldest => {
+                        debug!("mailbox is full, dropping its oldest message");
+                        if queue.messages.pop_front().is_none() {
+                            // a mailbox without capacity never holds a message
+                            return Err(SendError::Full(message));
+                        }
+                    }
+                }
+            }
+            queue.messages.push_back(message);
+        }
+        self.shared.notify.notify_waiters();
+        Ok(())
+    }
+}
+
+impl<M> Clone for MailboxSender<M> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<M> Drop for MailboxSender<M> {
+    fn drop(&mut self) {
+        // receivers waiting on an empty mailbox stop once nothing can send to
+        // it anymore
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.notify.notify_waiters();
+        }
+    }
+}
+
+impl<M> Debug for MailboxSender<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MailboxSender").finish_non_exhaustive()
+    }
+}
+
+/// Represents an error returned when sending a message, along with the
+/// message that couldn't be sent.
+#[derive(PartialEq, Eq, Clone)]
+pub enum SendError<M> {
+    /// The mailbox is full, and rejects new messages.
+    Full(M),
+    /// The mailbox has been dropped.
+    Closed(M),
+}
+
+impl<M> SendError<M> {
+    /// Returns the message that couldn't be sent.
+    pub fn into_inner(self) -> M {
+        match self {
+            Self::Full(message) | Self::Closed(message) => message,
+        }
+    }
+}
+
+impl<M> Debug for SendError<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full(_) => write!(f, "Full(..)"),
+            Self::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<M> Display for SendError<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full(_) => write!(f, "mailbox is full"),
+            Self::Closed(_) => write!(f, "mailbox is closed"),
+        }
+    }
+}
+
+impl<M> std::error::Error for SendError<M> {}
@@ -2,12 +2,22 @@ use std::fmt::Debug;
 use std::future::Future;
 use std::pin::Pin;
 
+use self::context::Context;
+use self::exit_reason::BoxError;
 use self::restartable::Restartable;
 use crate::{BackoffPolicy, RestartPolicy};
 
 pub mod backoff;
 pub mod backoff_policy;
+pub mod context;
+pub mod exit_reason;
+pub mod link;
+pub mod mailbox;
+pub mod registry;
 pub mod restartable;
+pub mod shutdown;
+pub mod shutdown_policy;
+pub mod template;
 pub mod watcher;
 
 /// A trait representing a worker that can be restarted.
@@ -16,7 +26,20 @@ pub trait Worker: Debug + Send + Restartable {
     /// worker is started, and can be called an infinite number of times if
     /// indefinite restarts are permitted. Therefore, it should be safe to call
     /// this repeatedly.
-    fn init(&self) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+    ///
+    /// The context provides a [`Shutdown`](crate::Shutdown) signal that's set
+    /// when the worker is asked to stop, after which the worker has as long as
+    /// its shutdown policy allows to finish.
+    ///
+    /// The worker's future returns an error if the worker failed, which is
+    /// reported as its [`ExitReason`](crate::ExitReason) along with panics.
+    /// The error is always a [`BoxError`](crate::BoxError), so that workers
+    /// can be kept as trait objects, which means errors of other types have to
+    /// be converted, such as with `?` or `map_err(Into::into)`.
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send + 'static>>;
 
     /// Returns the restart policy for worker.
     fn restart_policy(&self) -> RestartPolicy {
@@ -0,0 +1,115 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A worker's registration, along with the path of the worker that made it.
+struct Entry {
+    owner: String,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// Maps names to values registered by the workers running alongside each
+/// other in a supervisor, like Erlang/OTP's process registry.
+///
+/// A worker registers a value, such as the sending half of a channel, under a
+/// name with [`Context::register()`](crate::Context::register), and other
+/// workers look it up by name. Registrations last as long as the worker
+/// instance that made them: they're removed when it stops, so a worker that's
+/// restarted registers again from [`Worker::init()`](crate::Worker::init).
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(
+                self.entries()
+                    .iter()
+                    .map(|(name, entry)| (name, &entry.owner)),
+            )
+            .finish()
+    }
+}
+
+impl Registry {
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        // the map is left consistent by every operation, so it's still usable
+        // if a worker panicked while holding the lock
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns a clone of the value registered under the name, if there is
+    /// one and it's a `T`.
+    pub fn lookup<T>(&self, name: &str) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.entries()
+            .get(name)
+            .and_then(|entry| entry.value.downcast_ref::<T>().cloned())
+    }
+
+    /// Returns the path of the worker that registered the name, if it's
+    /// registered.
+    pub fn whereis(&self, name: &str) -> Option<String> {
+        self.entries().get(name).map(|entry| entry.owner.clone())
+    }
+
+    /// Registers the value under the name for the worker with the path,
+    /// replacing the worker's own earlier registration of the name.
+    pub(crate) fn register<T>(&self, owner: &str, name: &str, value: T) -> Result<(), RegistryError>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let mut entries = self.entries();
+        if entries.get(name).is_some_and(|entry| entry.owner != owner) {
+            return Err(RegistryError::AlreadyRegistered(name.to_string()));
+        }
+        entries.insert(
+            name.to_string(),
+            Entry {
+                owner: owner.to_string(),
+                value: Arc::new(value),
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the name if it was registered by the worker with the path,
+    /// returning true if it was.
+    pub(crate) fn unregister(&self, owner: &str, name: &str) -> bool {
+        let mut entries = self.entries();
+        if entries.get(name).is_some_and(|entry| entry.owner == owner) {
+            entries.remove(name);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes every name registered by the worker with the path, once it has
+    /// stopped.
+    pub(crate) fn unregister_all(&self, owner: &str) {
+        self.entries().retain(|_, entry| entry.owner != owner);
+    }
+}
+
+/// Represents an error returned when registering a name.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RegistryError {
+    /// Another worker has registered the name.
+    AlreadyRegistered(String),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyRegistered(name) => write!(f, "name {name:?} is already registered"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
@@ -1,15 +1,60 @@
 use super::backoff_policy::BackoffPolicy;
+use super::shutdown_policy::ShutdownPolicy;
+use crate::ExitStatus;
 
-/// Represents the restart policy for a process or worker task.
+/// Represents the restart policy for a process or worker task, which
+/// determines whether it's restarted after it stops.
+///
+/// A process or task stops abnormally when it fails with an error, panics, is
+/// killed by a signal, or exits with a non-zero exit code other than the one
+/// for stopping when asked to.
 #[derive(Debug, Default, PartialEq, Clone, Copy)]
 pub enum RestartPolicy {
-    /// Always restart the process or task.
+    /// Always restart the process or task, like Erlang/OTP's `permanent`.
     #[default]
     Always,
     /// Restart the process or task once.
     Once,
-    /// Never restart the process or task.
+    /// Never restart the process or task, like Erlang/OTP's `temporary`.
     Never,
+    /// Restart the process or task only when it stops abnormally, like
+    /// Erlang/OTP's `transient`.
+    Transient,
+    /// Restart the process or task up to the given number of times.
+    MaxRetries(usize),
+    /// Restart the process or task only when it exits with one of the exit
+    /// codes. Workers exit with the exit code for their
+    /// [`ExitReason`](crate::ExitReason). Use
+    /// [`on_exit_codes()`](Self::on_exit_codes) for codes built at runtime.
+    OnExitCodes(&'static [i32]),
+}
+
+impl RestartPolicy {
+    /// Returns a policy that restarts the process or task only when it exits
+    /// with one of the exit codes, like [`OnExitCodes`](Self::OnExitCodes),
+    /// for codes that are built at runtime, such as from a config file.
+    ///
+    /// The codes are leaked so the policy stays [`Copy`], so build the policy
+    /// once and keep it, rather than in each call to
+    /// [`Restartable::restart_policy()`].
+    pub fn on_exit_codes(codes: impl IntoIterator<Item = i32>) -> Self {
+        Self::OnExitCodes(Box::leak(codes.into_iter().collect()))
+    }
+
+    /// Returns true if a process or task that stopped with the exit status,
+    /// and has been restarted `restarts` times already, should be restarted.
+    pub(crate) fn should_restart(&self, exit_status: ExitStatus, restarts: usize) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Once => restarts == 0,
+            Self::Never => false,
+            Self::Transient => exit_status.is_abnormal(),
+            Self::MaxRetries(max_retries) => restarts < *max_retries,
+            Self::OnExitCodes(codes) => {
+                exit_status.code().is_some_and(|code| codes.contains(&code))
+            }
+        }
+    }
 }
 
 /// Trait for restartable processes or worker tasks.
@@ -23,4 +68,9 @@ pub trait Restartable {
     fn backoff_policy(&self) -> BackoffPolicy {
         BackoffPolicy::default()
     }
+
+    /// Returns the shutdown policy for the process or task.
+    fn shutdown_policy(&self) -> ShutdownPolicy {
+        ShutdownPolicy::default()
+    }
 }
@@ -0,0 +1,34 @@
+use std::future::Future;
+
+use tokio::sync::watch;
+
+/// A signal telling a worker that it's being asked to stop, which lets it
+/// flush buffers, commit offsets and close connections before it's stopped.
+/// How long the worker has to finish is determined by its
+/// [`ShutdownPolicy`](crate::ShutdownPolicy).
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub(crate) fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Self { rx })
+    }
+
+    /// Returns true if the worker has been asked to stop.
+    pub fn is_requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Returns a future that completes once the worker is asked to stop.
+    pub fn requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.rx.clone();
+        async move {
+            // an error means the watcher is gone, in which case there's nothing
+            // left to wait for
+            let _ = rx.wait_for(|requested| *requested).await;
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+/// Represents the shutdown policy for a process or worker task, which
+/// determines how it's stopped by its supervisor.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ShutdownPolicy {
+    /// Kill the process or task immediately, without giving it a chance to
+    /// clean up.
+    BrutalKill,
+    /// Ask the process or task to stop, and kill it if it hasn't stopped after
+    /// the timeout.
+    Timeout(Duration),
+    /// Ask the process or task to stop, and wait for as long as it takes. This
+    /// is the default for supervisors, which in turn stop their own children.
+    Infinity,
+}
+
+impl ShutdownPolicy {
+    /// Returns the more lenient of the two policies.
+    pub(crate) fn max(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Infinity, _) | (_, Self::Infinity) => Self::Infinity,
+            (Self::Timeout(a), Self::Timeout(b)) => Self::Timeout(a.max(b)),
+            (Self::Timeout(t), Self::BrutalKill) | (Self::BrutalKill, Self::Timeout(t)) => {
+                Self::Timeout(t)
+            }
+            (Self::BrutalKill, Self::BrutalKill) => Self::BrutalKill,
+        }
+    }
+}
+
+impl Default for ShutdownPolicy {
+    /// Returns the default `ShutdownPolicy`, which is a 5 second timeout.
+    fn default() -> Self {
+        Self::Timeout(Duration::from_secs(5))
+    }
+}
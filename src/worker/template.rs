@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use crate::Worker;
+
+type Template = Box<dyn Fn(&str) -> Box<dyn Worker> + Send>;
+
+/// The named templates a supervisor builds workers from at runtime, given the
+/// ID of the child being started.
+#[derive(Default)]
+pub struct Templates {
+    templates: HashMap<String, Template>,
+}
+
+impl Templates {
+    pub fn insert<W, F>(&mut self, name: &str, template: F)
+    where
+        W: Worker + 'static,
+        F: Fn(&str) -> W + Send + 'static,
+    {
+        self.templates.insert(
+            name.to_string(),
+            Box::new(move |id| Box::new(template(id)) as Box<dyn Worker>),
+        );
+    }
+
+    /// Builds a worker with the ID from the named template, if there is one.
+    pub fn build(&self, name: &str, id: &str) -> Option<Box<dyn Worker>> {
+        self.templates.get(name).map(|template| template(id))
+    }
+}
+
+impl Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.templates.keys()).finish()
+    }
+}
@@ -1,69 +1,976 @@
-use std::ops::Deref;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use log::debug;
-use tokio::runtime::Runtime;
-use tokio::task::JoinSet;
+use log::{debug, error};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::net::UnixDatagram;
+use tokio::runtime::{Builder, Handle};
+use tokio::sync::{oneshot, watch};
+use tokio::task::{Id, JoinSet};
 
-use crate::process::Process;
-use crate::worker::backoff::{Backoff, BackoffResult};
+use super::context::Context;
+use super::exit_reason::ExitReason;
+use super::link::Links;
+use super::mailbox::{Mailbox, MailboxSender};
+use super::registry::Registry;
+use super::shutdown::Shutdown;
+use super::template::Templates;
+use crate::error::SupertreeError;
+use crate::event::Notifier;
+use crate::handle::{self, HandleError, Request, Requests, Response};
+use crate::intensity::{RestartBudget, RestartIntensity};
+use crate::ipc::{self, MAX_FRAME_LEN};
+use crate::observer::ChildExit;
+use crate::process::{EXIT_INTENSITY_EXCEEDED, Process};
+use crate::signal::ShutdownSignals;
 use crate::worker::Restartable;
-use crate::{RestartPolicy, Worker};
+use crate::worker::backoff::{Backoff, BackoffResult};
+use crate::{RestartPolicy, ShutdownPolicy, Strategy, Worker};
+
+/// The name of the watcher among its supervisor's children, which is
+/// reserved so that no child's path is the watcher's.
+pub(crate) const WATCHER_NAME: &str = "watcher";
+
+/// How many messages from other processes are kept for a worker that hasn't
+/// received them yet.
+const INBOX_CAPACITY: usize = 1024;
 
-#[derive(Debug)]
 pub struct Watcher {
-    workers: Vec<Box<dyn Worker>>,
+    path: String,
+    workers: Vec<(String, Box<dyn Worker>)>,
+    strategy: Strategy,
+    /// The positions of the workers among their supervisor's children, in
+    /// the order the children were added, whether they're workers or
+    /// supervisors.
+    positions: Vec<usize>,
+    /// How many children the supervisor was built with, which the workers
+    /// started from templates come after.
+    members: usize,
+    /// The names and positions of the supervisor's child supervisors, which
+    /// the process group runs, and which the supervisor's handle manages
+    /// through the watcher.
+    supervisors: Vec<(String, usize)>,
+    intensity: Option<RestartIntensity>,
+    shutdown_policy: ShutdownPolicy,
+    templates: Templates,
+    /// The control socket of the supervisor's handle, along with its name.
+    control: Option<(String, UnixListener)>,
+    max_children: Option<usize>,
+    remove_stopped: bool,
+    /// The names registered by the running workers.
+    registry: Registry,
+    /// The monitors and links between the running workers.
+    links: Links,
+    notifier: Arc<Notifier>,
+    /// Whether the watcher runs in a thread of its supervisor's process,
+    /// rather than in a process of its own, in which case it's asked to stop
+    /// by its supervisor instead of by signals.
+    in_thread: bool,
+    /// The runtime the workers run on, which is shared with the rest of the
+    /// process, rather than one of the watcher's own.
+    runtime: Option<Handle>,
+}
+
+impl Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("path", &self.path)
+            .field("workers", &self.workers)
+            .field("strategy", &self.strategy)
+            .field("intensity", &self.intensity)
+            .field("shutdown_policy", &self.shutdown_policy)
+            .field("templates", &self.templates)
+            .field("registry", &self.registry)
+            .field("links", &self.links)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A frame the process group addresses to the watcher itself.
+enum Control {
+    /// The group asks the watcher to stop, for a watcher running in a thread.
+    Shutdown,
+    Restart(ipc::Restart),
+    /// One of the supervisor's child supervisors started or stopped.
+    Child(ipc::ChildState),
+}
+
+enum State {
+    Running,
+    /// The worker has been asked to stop, and is restarted after the delay
+    /// once it has, if there's a delay.
+    Stopping(Option<Duration>),
+    /// The worker has stopped, and is restarted once the process group has
+    /// decided which of the supervisor's other children are restarted along
+    /// with it.
+    Pending,
+    /// The worker is backing off, and is started again at the instant.
+    Waiting(Instant),
+    Stopped,
+}
+
+/// One of the supervisor's child supervisors, as seen by the supervisor's
+/// handle.
+struct Sibling {
+    id: String,
+    /// The position of the supervisor among its parent's children.
+    position: usize,
+    running: bool,
+    /// Handles waiting for the supervisor to stop after terminating it.
+    on_stopped: Vec<oneshot::Sender<Response>>,
+}
+
+struct Slot {
+    id: String,
+    /// The path of the worker in the tree, such as `root/db/pool-3`.
+    path: String,
+    /// The position of the worker among its supervisor's children.
+    position: usize,
+    backoff: Backoff<dyn Worker>,
+    /// Whether the worker was started from a template, rather than added when
+    /// the tree was built, in which case it isn't kept once the watcher stops.
+    template: bool,
+    state: State,
+    stop: Option<watch::Sender<bool>>,
+    /// Handles waiting for the worker to stop after terminating it.
+    on_stopped: Vec<oneshot::Sender<Response>>,
+    /// Messages sent to the worker from other processes, which outlive each
+    /// started instance of the worker.
+    inbox: Mailbox<Vec<u8>>,
+    inbox_sender: MailboxSender<Vec<u8>>,
+}
+
+impl Slot {
+    fn new(
+        parent: &str,
+        id: String,
+        position: usize,
+        worker: Box<dyn Worker>,
+        template: bool,
+    ) -> Self {
+        let inbox = Mailbox::new(INBOX_CAPACITY);
+        Self {
+            inbox_sender: inbox.sender(),
+            inbox,
+            path: format!("{parent}/{id}"),
+            position,
+            id,
+            backoff: Backoff::new(worker),
+            template,
+            state: State::Stopped,
+            stop: None,
+            on_stopped: vec![],
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        !matches!(self.state, State::Stopped)
+    }
+
+    /// Asks the worker to stop, giving it as long as its shutdown policy
+    /// allows. A worker waiting to be restarted is no longer restarted,
+    /// unless it's to be restarted after all.
+    fn stop(&mut self, restart_after: Option<Duration>) {
+        match self.state {
+            State::Running => {
+                debug!("stopping worker={}", self.path);
+                if let Some(stop) = self.stop.as_ref() {
+                    let _ = stop.send(true);
+                }
+                self.state = State::Stopping(restart_after);
+            }
+            State::Pending | State::Waiting(_) if restart_after.is_none() => {
+                self.state = State::Stopped
+            }
+            _ => {}
+        }
+    }
 }
 
 impl Watcher {
-    pub fn new(workers: Vec<Box<dyn Worker>>) -> Self {
-        Self { workers }
+    pub fn new(
+        path: String,
+        workers: Vec<(String, Box<dyn Worker>)>,
+        strategy: Strategy,
+        intensity: Option<RestartIntensity>,
+    ) -> Self {
+        // the watcher is given as long to stop as its most lenient worker
+        let shutdown_policy = workers
+            .iter()
+            .map(|(_, worker)| worker.shutdown_policy())
+            .reduce(ShutdownPolicy::max)
+            .unwrap_or_default();
+        Self {
+            path,
+            positions: (0..workers.len()).collect(),
+            members: workers.len(),
+            supervisors: vec![],
+            workers,
+            strategy,
+            intensity,
+            shutdown_policy,
+            templates: Templates::default(),
+            control: None,
+            max_children: None,
+            remove_stopped: false,
+            registry: Registry::default(),
+            links: Links::default(),
+            notifier: Arc::default(),
+            in_thread: false,
+            runtime: None,
+        }
     }
 
-    fn start_worker(joinset: &mut JoinSet<Box<dyn Worker>>, worker: Box<dyn Worker>) {
-        debug!("starting worker={worker:?}");
-        joinset.spawn(async move {
-            let mut backoff = Backoff::new(worker);
-            loop {
-                let f = backoff.init();
-                f.await;
-                if let BackoffResult::RetryAfterDelay(delay) = backoff.maybe_delay() {
+    /// Sets the positions of the workers among their supervisor's children,
+    /// out of the number of children the supervisor was built with.
+    pub(crate) fn with_positions(mut self, positions: Vec<usize>, members: usize) -> Self {
+        self.positions = positions;
+        self.members = members;
+        self
+    }
+
+    /// Sets the names and positions of the supervisor's child supervisors,
+    /// which are listed, terminated and started through the supervisor's
+    /// handle along with the workers.
+    pub(crate) fn with_supervisors(mut self, supervisors: Vec<(String, usize)>) -> Self {
+        self.supervisors = supervisors;
+        self
+    }
+
+    /// Runs the watcher in a thread of its supervisor's process, which leaves
+    /// the process's signals to the process group running in it.
+    pub(crate) fn with_in_thread(mut self, in_thread: bool) -> Self {
+        self.in_thread = in_thread;
+        self
+    }
+
+    /// Runs the workers on the runtime, if there is one, rather than on a
+    /// runtime of the watcher's own.
+    pub(crate) fn with_runtime(mut self, runtime: Option<Handle>) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Notifies the observer and the event subscribers of the lifecycle of
+    /// the workers.
+    pub(crate) fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Arc::new(notifier);
+        self
+    }
+
+    /// Serves requests from the supervisor's handle on the control socket,
+    /// starting workers from the templates. The watcher keeps running while
+    /// it has no workers, until it's asked to shut down.
+    pub fn with_control(mut self, name: &str, control: UnixListener, templates: Templates) -> Self {
+        self.control = Some((name.to_string(), control));
+        self.templates = templates;
+        self
+    }
+
+    /// Limits how many workers may be started from the templates, counting
+    /// those that have stopped but haven't been deleted.
+    pub fn with_max_children(mut self, max_children: Option<usize>) -> Self {
+        self.max_children = max_children;
+        self
+    }
+
+    /// Removes children that are given up on, rather than keeping them until
+    /// they're deleted.
+    pub fn with_remove_stopped(mut self, remove_stopped: bool) -> Self {
+        self.remove_stopped = remove_stopped;
+        self
+    }
+
+    /// Removes a stopped worker, moving down the workers after it.
+    fn remove_worker(tasks: &mut HashMap<Id, usize>, slots: &mut Vec<Slot>, index: usize) {
+        slots.remove(index);
+        for slot_index in tasks.values_mut() {
+            if *slot_index > index {
+                *slot_index -= 1;
+            }
+        }
+    }
+
+    /// Puts the message in a frame routed to the watcher in the inbox of the
+    /// worker it's addressed to. Returns the frame instead if the process
+    /// group addressed it to the watcher itself.
+    fn deliver(slots: &[Slot], frame: &[u8]) -> Option<Control> {
+        let Some((destination, payload)) = ipc::decode_frame(frame) else {
+            debug!("dropping malformed frame of {} bytes", frame.len());
+            return None;
+        };
+        if destination == ipc::SHUTDOWN {
+            return Some(Control::Shutdown);
+        }
+        if destination == ipc::RESTART {
+            let restart = ipc::Restart::decode(payload);
+            if restart.is_none() {
+                debug!("dropping malformed restart of {} bytes", payload.len());
+            }
+            return restart.map(Control::Restart);
+        }
+        if destination == ipc::CHILD {
+            let state = ipc::ChildState::decode(payload);
+            if state.is_none() {
+                debug!("dropping malformed child state of {} bytes", payload.len());
+            }
+            return state.map(Control::Child);
+        }
+        match slots.iter().find(|slot| slot.path == destination) {
+            Some(slot) => {
+                if let Err(err) = slot.inbox_sender.send(payload.to_vec()) {
+                    debug!("dropping message for worker={destination}: {err}");
+                }
+            }
+            None => debug!("dropping message for unknown worker={destination}"),
+        }
+        None
+    }
+
+    /// Asks the process group to restart the worker after the delay, along
+    /// with the children its strategy restarts with it. Returns false if the
+    /// group couldn't be asked, in which case the watcher restarts the worker
+    /// itself, counting the restart against its own budget.
+    fn escalate(&self, slot: &Slot, delay: Duration) -> bool {
+        let restart = ipc::Restart {
+            name: slot.id.clone(),
+            delay,
+            range: slot.position..slot.position + 1,
+        };
+        let result = ipc::uplink()
+            .ok_or(ipc::IpcError::Disconnected)
+            .and_then(|uplink| restart.send(&uplink));
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                debug!(
+                    "couldn't ask process group to restart worker={} err={err}",
+                    slot.path
+                );
+                false
+            }
+        }
+    }
+
+    /// Asks the process group to terminate or start the child supervisor with
+    /// the name, for the supervisor's handle.
+    fn ask_group(destination: &str, name: &str) -> Result<(), ipc::IpcError> {
+        let frame = ipc::encode_frame(destination, name.as_bytes())?;
+        let uplink = ipc::uplink().ok_or(ipc::IpcError::Disconnected)?;
+        uplink.send(&frame).map(|_| ()).map_err(ipc::IpcError::Io)
+    }
+
+    /// Restarts the workers the process group decided to restart after the
+    /// delay. The worker waiting to be restarted is started again, and the
+    /// running workers in the restart's range are asked to stop, and are
+    /// started again once they have.
+    fn restart(
+        &self,
+        joinset: &mut JoinSet<ExitReason>,
+        tasks: &mut HashMap<Id, usize>,
+        slots: &mut [Slot],
+        restart: ipc::Restart,
+    ) {
+        for index in 0..slots.len() {
+            let slot = &mut slots[index];
+            if slot.id == restart.name {
+                if let State::Pending = slot.state {
                     debug!(
-                        "worker stopped, retrying after delay={delay:?} for worker={:?}",
-                        backoff.deref()
+                        "worker stopped, retrying after delay={:?} for worker={}",
+                        restart.delay, slot.path
                     );
-                    tokio::time::sleep(delay).await;
+                    slot.state = State::Stopped;
+                    self.notifier
+                        .restart_scheduled(&slot.path, std::process::id(), restart.delay);
+                    self.start_worker(joinset, tasks, slots, index, restart.delay);
+                }
+            } else if restart.range.contains(&slot.position) {
+                slot.stop(Some(restart.delay));
+            }
+        }
+    }
+
+    /// Handles a request from the supervisor's handle, returning the response
+    /// unless it's sent once a terminated worker has stopped.
+    fn handle_request(
+        &self,
+        joinset: &mut JoinSet<ExitReason>,
+        tasks: &mut HashMap<Id, usize>,
+        slots: &mut Vec<Slot>,
+        siblings: &mut Vec<Sibling>,
+        request: Request,
+        reply: oneshot::Sender<Response>,
+    ) -> Option<(oneshot::Sender<Response>, Response)> {
+        let position = |slots: &[Slot], id: &str| slots.iter().position(|slot| slot.id == id);
+        let sibling = match &request {
+            Request::TerminateChild(id) | Request::RestartChild(id) | Request::DeleteChild(id) => {
+                siblings.iter().position(|sibling| &sibling.id == id)
+            }
+            _ => None,
+        };
+        if let Some(index) = sibling {
+            return Self::handle_sibling_request(siblings, index, request, reply);
+        }
+        let response = match request {
+            Request::StartChild { template, id } => {
+                // the ID is part of the worker's path, so it can't be empty,
+                // contain the path separator or be the watcher's own name
+                if id.is_empty() || id.contains('/') || id == WATCHER_NAME {
+                    Response::Err(HandleError::InvalidName(id))
+                } else if position(slots, &id).is_some()
+                    || siblings.iter().any(|sibling| sibling.id == id)
+                {
+                    Response::Err(HandleError::AlreadyExists(id))
+                } else if self
+                    .max_children
+                    .is_some_and(|max_children| slots.len() >= max_children)
+                {
+                    Response::Err(HandleError::MaxChildren)
+                } else if let Some(worker) = self.templates.build(&template, &id) {
+                    // workers started from templates come after every other
+                    // child, in the order they're started
+                    let position = slots
+                        .iter()
+                        .map(|slot| slot.position + 1)
+                        .fold(self.members, usize::max);
+                    slots.push(Slot::new(&self.path, id, position, worker, true));
+                    let index = slots.len() - 1;
+                    self.start_worker(joinset, tasks, slots, index, Duration::ZERO);
+                    Response::Ok
                 } else {
-                    break;
+                    Response::Err(HandleError::UnknownTemplate(template))
+                }
+            }
+            Request::TerminateChild(id) => match position(slots, &id) {
+                Some(index) => match slots[index].state {
+                    State::Stopped => Response::Ok,
+                    State::Pending | State::Waiting(_) => {
+                        slots[index].state = State::Stopped;
+                        Response::Ok
+                    }
+                    _ => {
+                        // the worker is no longer restarted once it stops
+                        let slot = &mut slots[index];
+                        slot.stop(None);
+                        slot.state = State::Stopping(None);
+                        slot.on_stopped.push(reply);
+                        return None;
+                    }
+                },
+                None => Response::Err(HandleError::NotFound(id)),
+            },
+            Request::RestartChild(id) => match position(slots, &id) {
+                Some(index) => match slots[index].state {
+                    State::Stopped => {
+                        self.start_worker(joinset, tasks, slots, index, Duration::ZERO);
+                        Response::Ok
+                    }
+                    _ => Response::Err(HandleError::Running(id)),
+                },
+                None => Response::Err(HandleError::NotFound(id)),
+            },
+            Request::DeleteChild(id) => match position(slots, &id) {
+                Some(index) => match slots[index].state {
+                    State::Stopped => {
+                        Self::remove_worker(tasks, slots, index);
+                        Response::Ok
+                    }
+                    _ => Response::Err(HandleError::Running(id)),
+                },
+                None => Response::Err(HandleError::NotFound(id)),
+            },
+            Request::WhichChildren => {
+                // workers and supervisors are listed in the order they were
+                // added
+                let mut children: Vec<(usize, &str, bool)> =
+                    slots
+                        .iter()
+                        .map(|slot| (slot.position, slot.id.as_str(), slot.is_running()))
+                        .chain(siblings.iter().map(|sibling| {
+                            (sibling.position, sibling.id.as_str(), sibling.running)
+                        }))
+                        .collect();
+                children.sort_by_key(|(position, ..)| *position);
+                Response::children(children.into_iter().map(|(_, id, running)| (id, running)))
+            }
+            Request::CountChildren => Response::count(
+                slots.len() + siblings.len(),
+                slots.iter().filter(|slot| slot.is_running()).count()
+                    + siblings.iter().filter(|sibling| sibling.running).count(),
+            ),
+            Request::Shutdown => unreachable!("shutdown is handled by the watcher loop"),
+        };
+        Some((reply, response))
+    }
+
+    /// Handles a request from the supervisor's handle for one of its child
+    /// supervisors, which the watcher asks the process group to terminate or
+    /// start. Returns the response unless it's sent once a terminated
+    /// supervisor has stopped.
+    fn handle_sibling_request(
+        siblings: &mut Vec<Sibling>,
+        index: usize,
+        request: Request,
+        reply: oneshot::Sender<Response>,
+    ) -> Option<(oneshot::Sender<Response>, Response)> {
+        let sibling = &mut siblings[index];
+        let response = match request {
+            Request::TerminateChild(_) if !sibling.running => Response::Ok,
+            Request::TerminateChild(id) => match Self::ask_group(ipc::TERMINATE, &id) {
+                Ok(()) => {
+                    sibling.on_stopped.push(reply);
+                    return None;
+                }
+                Err(err) => Response::Err(HandleError::Io(io::Error::other(err))),
+            },
+            Request::RestartChild(id) if sibling.running => Response::Err(HandleError::Running(id)),
+            Request::RestartChild(id) => match Self::ask_group(ipc::START, &id) {
+                Ok(()) => {
+                    sibling.running = true;
+                    Response::Ok
                 }
+                Err(err) => Response::Err(HandleError::Io(io::Error::other(err))),
+            },
+            Request::DeleteChild(id) if sibling.running => Response::Err(HandleError::Running(id)),
+            Request::DeleteChild(_) => {
+                siblings.remove(index);
+                Response::Ok
             }
-            backoff.into_inner()
-        });
+            _ => unreachable!("only requests for a child by its ID name a supervisor"),
+        };
+        Some((reply, response))
     }
 
-    fn start_workers(&mut self) -> JoinSet<Box<dyn Worker>> {
-        let mut joinset = JoinSet::new();
-        let workers = std::mem::take(&mut self.workers);
-        for worker in workers.into_iter() {
-            Self::start_worker(&mut joinset, worker);
+    /// Starts the worker, or has it wait for the delay first, in which case
+    /// it's started by the watcher loop once the delay is over.
+    fn start_worker(
+        &self,
+        joinset: &mut JoinSet<ExitReason>,
+        tasks: &mut HashMap<Id, usize>,
+        slots: &mut [Slot],
+        index: usize,
+        delay: Duration,
+    ) {
+        let slot = &mut slots[index];
+        // the worker isn't initialized or recorded as started until it's
+        // done backing off, so it can't be found by its siblings before then
+        if !delay.is_zero() {
+            slot.state = State::Waiting(Instant::now() + delay);
+            return;
         }
-        joinset
+        debug!("starting worker={}", slot.path);
+        let (stop, shutdown) = Shutdown::new();
+        let shutdown_policy = slot.backoff.shutdown_policy();
+        let context = Context::new(
+            shutdown.clone(),
+            slot.id.clone(),
+            slot.path.clone(),
+            self.registry.clone(),
+            slot.inbox.clone(),
+            self.links.clone(),
+        );
+        let mut crashed = self.links.started(&slot.path);
+        // a panic while the worker builds its future only takes down the
+        // worker, like a panic in the future itself
+        let init = std::panic::catch_unwind(AssertUnwindSafe(|| slot.backoff.init(context)));
+        let notifier = self.notifier.clone();
+        let path = slot.path.clone();
+        // the worker's messages are sent on the watcher's link, whichever
+        // thread of the runtime the worker runs on
+        let handle = joinset.spawn(ipc::with_uplink(async move {
+            notifier.started(&path, std::process::id());
+            let mut f = match init {
+                Ok(f) => f,
+                Err(payload) => return ExitReason::Panic(panic_message(payload)),
+            };
+            let result = tokio::select! {
+                result = &mut f => result,
+                Ok(message) = &mut crashed => return ExitReason::Error(message.into()),
+                _ = shutdown.requested() => {
+                    // give the worker time to finish on its own before it's dropped
+                    match shutdown_policy {
+                        ShutdownPolicy::BrutalKill => return ExitReason::Shutdown,
+                        ShutdownPolicy::Timeout(timeout) => {
+                            match tokio::time::timeout(timeout, f).await {
+                                Ok(result) => result,
+                                Err(_) => {
+                                    debug!("worker didn't stop within timeout={timeout:?}");
+                                    return ExitReason::Shutdown;
+                                }
+                            }
+                        }
+                        ShutdownPolicy::Infinity => f.await,
+                    }
+                }
+            };
+            ExitReason::from_result(result, shutdown.is_requested())
+        }));
+        tasks.insert(handle.id(), index);
+        slot.state = State::Running;
+        slot.stop = Some(stop);
+    }
+
+    fn start(&mut self) -> Result<i32, SupertreeError> {
+        // a watcher sharing the process's runtime runs its workers on it, and
+        // otherwise starts a runtime of its own, which is kept until the
+        // workers have stopped
+        let (runtime, _own) = match self.runtime.clone() {
+            Some(runtime) => (runtime, None),
+            None => {
+                debug!("starting tokio runtime");
+                // the runtime's threads send the workers' messages on the
+                // watcher's link, which is the thread's own in a watcher
+                // running in a thread
+                let uplink = ipc::uplink();
+                let rt = Builder::new_multi_thread()
+                    .enable_all()
+                    .on_thread_start(move || {
+                        if let Some(uplink) = &uplink {
+                            ipc::set_thread_uplink(uplink.clone());
+                        }
+                    })
+                    .build()
+                    .map_err(|err| SupertreeError::Runtime(self.path.clone(), err))?;
+                (rt.handle().clone(), Some(rt))
+            }
+        };
+        let strategy = self.strategy;
+        let mut budget = RestartBudget::new(self.intensity);
+        runtime.block_on(async move {
+            // a watcher running in a thread leaves the process's signals to
+            // the process group running in it
+            let signals = match self.in_thread {
+                true => None,
+                false => Some(
+                    ShutdownSignals::install_for_workers()
+                        .map_err(|err| SupertreeError::Signals(self.path.clone(), err))?,
+                ),
+            };
+            let wake = signals
+                .as_ref()
+                .map(|signals| AsyncFd::with_interest(signals.wake_fd(), Interest::READABLE))
+                .transpose()
+                .map_err(|err| SupertreeError::Signals(self.path.clone(), err))?;
+            // the workers and the control socket are kept, so that the
+            // watcher can be run again when it's restarted in a thread
+            let workers = std::mem::take(&mut self.workers);
+            let control = self
+                .control
+                .as_ref()
+                .map(|(_, control)| control.try_clone());
+            let mut shutting_down = false;
+            let mut exit_code = 0;
+
+            // workers added when the tree was built are identified by their
+            // names
+            let mut slots: Vec<Slot> = workers
+                .into_iter()
+                .enumerate()
+                .map(|(index, (name, worker))| {
+                    let position = self.positions.get(index).copied().unwrap_or(index);
+                    Slot::new(&self.path, name, position, worker, false)
+                })
+                .collect();
+            let mut siblings: Vec<Sibling> = self
+                .supervisors
+                .iter()
+                .map(|(id, position)| Sibling {
+                    id: id.clone(),
+                    position: *position,
+                    running: true,
+                    on_stopped: vec![],
+                })
+                .collect();
+            let mut requests = match control
+                .map(|control| control.and_then(handle::serve))
+                .transpose()
+            {
+                Ok(requests) => requests,
+                Err(err) => {
+                    error!("failed to serve supervisor handle requests err={err}");
+                    None
+                }
+            };
+            // messages for the workers are routed to the watcher by the
+            // process group that forked it
+            let uplink = match ipc::uplink()
+                .map(|uplink| uplink.try_clone().and_then(UnixDatagram::from_std))
+                .transpose()
+            {
+                Ok(uplink) => uplink,
+                Err(err) => {
+                    error!("failed to receive messages from other processes err={err}");
+                    None
+                }
+            };
+            let mut buf = vec![0; MAX_FRAME_LEN];
+            let mut tasks = HashMap::new();
+            let mut joinset = JoinSet::new();
+            for index in 0..slots.len() {
+                self.start_worker(&mut joinset, &mut tasks, &mut slots, index, Duration::ZERO);
+            }
+
+            loop {
+                // with a handle, the watcher waits for workers to be started
+                // until it's asked to shut down
+                let pending = slots
+                    .iter()
+                    .any(|slot| matches!(slot.state, State::Pending | State::Waiting(_)));
+                if joinset.is_empty() && !pending && (requests.is_none() || shutting_down) {
+                    break;
+                }
+                let next_start = slots
+                    .iter()
+                    .filter_map(|slot| match slot.state {
+                        State::Waiting(at) => Some(at),
+                        _ => None,
+                    })
+                    .min();
+                let result = tokio::select! {
+                    Some(result) = joinset.join_next_with_id() => result,
+                    _ = next_backoff(next_start) => {
+                        let now = Instant::now();
+                        for index in 0..slots.len() {
+                            if matches!(slots[index].state, State::Waiting(at) if at <= now) {
+                                self.start_worker(
+                                    &mut joinset, &mut tasks, &mut slots, index, Duration::ZERO,
+                                );
+                            }
+                        }
+                        continue;
+                    }
+                    Some(len) = next_frame(uplink.as_ref(), &mut buf) => {
+                        match Self::deliver(&slots, &buf[..len]) {
+                            _ if shutting_down => {}
+                            Some(Control::Shutdown) => {
+                                debug!("asked to stop by process group, stopping workers");
+                                shutting_down = true;
+                                slots.iter_mut().for_each(|slot| slot.stop(None));
+                            }
+                            Some(Control::Restart(restart)) => {
+                                self.restart(&mut joinset, &mut tasks, &mut slots, restart);
+                            }
+                            Some(Control::Child(state)) => {
+                                let sibling = siblings
+                                    .iter_mut()
+                                    .find(|sibling| sibling.id == state.name);
+                                if let Some(sibling) = sibling {
+                                    sibling.running = state.running;
+                                    if !state.running {
+                                        for reply in sibling.on_stopped.drain(..) {
+                                            let _ = reply.send(Response::Ok);
+                                        }
+                                    }
+                                }
+                            }
+                            None => {}
+                        }
+                        continue;
+                    }
+                    Some((request, reply)) = next_request(&mut requests) => {
+                        if let Request::Shutdown = request {
+                            debug!("shutdown requested by handle, stopping workers");
+                            self.notifier.shutdown(&self.path);
+                            shutting_down = true;
+                            slots.iter_mut().for_each(|slot| slot.stop(None));
+                            let _ = reply.send(Response::Ok);
+                        } else if let Some((reply, response)) = self.handle_request(
+                            &mut joinset, &mut tasks, &mut slots, &mut siblings, request, reply,
+                        ) {
+                            let _ = reply.send(response);
+                        }
+                        continue;
+                    }
+                    _ = next_shutdown(signals.as_ref(), wake.as_ref()), if !shutting_down => {
+                        debug!("received SIGTERM or SIGINT, stopping workers");
+                        shutting_down = true;
+                        slots.iter_mut().for_each(|slot| slot.stop(None));
+                        continue;
+                    }
+                };
+                let (id, reason) = match result {
+                    Ok((id, reason)) => (id, reason),
+                    Err(err) if err.is_cancelled() => continue,
+                    Err(err) => (err.id(), ExitReason::Panic(panic_message(err.into_panic()))),
+                };
+                let Some(index) = tasks.remove(&id) else {
+                    continue;
+                };
+                let reason = Arc::new(reason);
+                self.links
+                    .stopped(&slots[index].path, &slots[index].id, &reason);
+                // the stopped instance's names are removed before it's
+                // restarted or backs off, so lookups never find a stale value
+                self.registry.unregister_all(&slots[index].path);
+                // a panic only takes down the worker that panicked, which is
+                // restarted like any other worker that stopped
+                if reason.is_abnormal() {
+                    error!("worker={} failed with {reason}", slots[index].path);
+                } else {
+                    debug!("worker={} stopped with {reason}", slots[index].path);
+                }
+                let pid = std::process::id();
+                self.notifier
+                    .exited(&slots[index].path, pid, &ChildExit::Worker(&reason));
+                for reply in slots[index].on_stopped.drain(..) {
+                    let _ = reply.send(Response::Ok);
+                }
+                match std::mem::replace(&mut slots[index].state, State::Stopped) {
+                    State::Stopping(Some(delay)) if !shutting_down => {
+                        self.notifier
+                            .restart_scheduled(&slots[index].path, pid, delay);
+                        self.start_worker(&mut joinset, &mut tasks, &mut slots, index, delay);
+                        continue;
+                    }
+                    State::Stopping(_) => continue,
+                    _ if shutting_down => continue,
+                    _ => {}
+                }
+                let BackoffResult::RetryAfterDelay(delay) =
+                    slots[index].backoff.maybe_delay(reason.exit_status())
+                else {
+                    // the watcher exits with the reason of the first worker that
+                    // failed and wasn't restarted
+                    if reason.is_abnormal() {
+                        self.notifier
+                            .gave_up(&slots[index].path, pid, &ChildExit::Worker(&reason));
+                        if exit_code == 0 {
+                            exit_code = reason.exit_code();
+                        }
+                    }
+                    if self.remove_stopped {
+                        Self::remove_worker(&mut tasks, &mut slots, index);
+                    }
+                    continue;
+                };
+                // the process group counts the restart against the
+                // supervisor's intensity, and decides which of its other
+                // children the strategy restarts along with the worker
+                if self.escalate(&slots[index], delay) {
+                    slots[index].state = State::Pending;
+                    continue;
+                }
+                if !budget.record() {
+                    debug!("restart intensity exceeded, stopping workers");
+                    self.notifier.intensity_exceeded(&self.path);
+                    shutting_down = true;
+                    exit_code = EXIT_INTENSITY_EXCEEDED;
+                    slots.iter_mut().for_each(|slot| slot.stop(None));
+                    continue;
+                }
+                debug!(
+                    "worker stopped, retrying after delay={delay:?} for worker={}",
+                    slots[index].path
+                );
+                self.notifier
+                    .restart_scheduled(&slots[index].path, pid, delay);
+                // running siblings are asked to stop, and are restarted once they
+                // have. The strategy works over the workers' positions, which
+                // differ from their slots once workers are added or removed
+                let range = strategy.restart_range(slots[index].position, usize::MAX);
+                for (sibling, slot) in slots.iter_mut().enumerate() {
+                    if sibling != index && range.contains(&slot.position) {
+                        slot.stop(Some(delay));
+                    }
+                }
+                self.start_worker(&mut joinset, &mut tasks, &mut slots, index, delay);
+            }
+            if shutting_down && exit_code == 0 {
+                exit_code = ExitReason::Shutdown.exit_code();
+            }
+            self.workers = slots
+                .into_iter()
+                .filter(|slot| !slot.template)
+                .map(|slot| (slot.id, slot.backoff.into_inner()))
+                .collect();
+            Ok(exit_code)
+        })
+    }
+}
+
+/// Waits until the instant at which the next worker is done backing off, if
+/// any worker is backing off.
+async fn next_backoff(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Returns the length of the next frame routed to the watcher, if it's linked
+/// to a process group.
+async fn next_frame(uplink: Option<&UnixDatagram>, buf: &mut [u8]) -> Option<usize> {
+    match uplink {
+        Some(uplink) => match uplink.recv(buf).await {
+            Ok(len) => Some(len),
+            Err(err) => {
+                debug!("failed to receive frame err={err}");
+                None
+            }
+        },
+        None => std::future::pending().await,
     }
+}
 
-    fn start(&mut self) {
-        debug!("starting tokio runtime");
-        let rt = Runtime::new().expect("failed to start runtime");
-        rt.block_on(async move {
-            let mut joinset = self.start_workers();
-            while let Some(Ok(worker)) = joinset.join_next().await {
-                debug!("joined worker={:?}", worker);
+/// Waits until SIGTERM or SIGINT asks the watcher to shut down, if it handles
+/// them, woken up by the self-pipe their handlers write to.
+async fn next_shutdown(signals: Option<&ShutdownSignals>, wake: Option<&AsyncFd<BorrowedFd<'_>>>) {
+    let (Some(signals), Some(wake)) = (signals, wake) else {
+        return std::future::pending().await;
+    };
+    while !signals.requested() {
+        match wake.readable().await {
+            Ok(mut guard) => {
+                guard.clear_ready();
+                signals.drain();
+            }
+            Err(err) => {
+                debug!("failed to wait for signals err={err}");
+                return std::future::pending().await;
             }
-        });
+        }
+    }
+}
+
+/// Returns the next request from the supervisor's handle, if it has one.
+async fn next_request(
+    requests: &mut Option<Requests>,
+) -> Option<(Request, oneshot::Sender<Response>)> {
+    match requests {
+        Some(requests) => requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Returns the message a worker panicked with, if it's a string.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "Box<dyn Any>".to_string(),
+        },
     }
 }
 
 impl Process for Watcher {
-    fn start(&mut self) {
-        self.start();
+    fn start(&mut self) -> Result<i32, SupertreeError> {
+        self.start()
+    }
+
+    fn listeners(&self) -> Vec<(String, RawFd)> {
+        self.control
+            .iter()
+            .map(|(name, control)| (name.clone(), control.as_raw_fd()))
+            .collect()
     }
 }
 
@@ -71,4 +978,8 @@ impl Restartable for Watcher {
     fn restart_policy(&self) -> crate::RestartPolicy {
         RestartPolicy::Never
     }
+
+    fn shutdown_policy(&self) -> ShutdownPolicy {
+        self.shutdown_policy
+    }
 }
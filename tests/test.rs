@@ -1,5 +1,16 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use log::debug;
-use supertrees::{RestartPolicy, Restartable, Worker};
+use supertrees::{
+    BackoffPolicy, BoxError, ChildExit, Codec, Context, ExitReason, ExitStatus, ExitSummary,
+    GenServer, GenServerClient, GenServerError, GenServerWorker, IpcError, IpcSender, LinkError,
+    Mailbox, MailboxSender, OverflowPolicy, RestartPolicy, Restartable, SendError,
+    SupervisionEvent, SupervisionEventKind, SupervisorHandle, SupervisorObserver, ThreadPolicy,
+    Worker,
+};
 use test_log::test;
 #[derive(Debug)]
 struct W {
@@ -13,10 +24,15 @@ impl W {
 }
 
 impl Worker for W {
-    fn init(&self) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>> {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
         let num = self.num;
         Box::pin(async move {
             println!("hi, I'm woooorker num={num} :)");
+            Ok(())
         })
     }
 }
@@ -27,24 +43,2077 @@ impl Restartable for W {
     }
 }
 
+/// Supervisors install process-wide signal handlers, so trees started by tests
+/// running concurrently must not overlap.
+static TREE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 #[test]
 fn test_supertree() {
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
     use supertrees::Supertree;
     let root = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
         .add_worker(W::new(1))
         .add_worker(W::new(2))
         .add_worker(W::new(3))
         .add_supervisor(|s| {
-            s.add_worker(W::new(4))
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_worker(W::new(4))
                 .add_worker(W::new(5))
                 .add_worker(W::new(6))
                 .add_supervisor(|s| {
-                    s.add_worker(W::new(7))
+                    s.with_restart_policy(RestartPolicy::Transient)
+                        .add_worker(W::new(7))
                         .add_worker(W::new(8))
                         .add_worker(W::new(9))
                 })
         });
     debug!("supertree={root:#?}");
-    root.start();
+    root.start().unwrap();
     println!("done");
 }
+
+/// Appends its name to a file each time it's started, then sleeps until it's
+/// done or asked to stop.
+#[derive(Debug)]
+struct Recorder {
+    path: PathBuf,
+    name: &'static str,
+    sleep: Duration,
+    restart_policy: RestartPolicy,
+}
+
+impl Recorder {
+    fn new(
+        path: &Path,
+        name: &'static str,
+        sleep: Duration,
+        restart_policy: RestartPolicy,
+    ) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            name,
+            sleep,
+            restart_policy,
+        }
+    }
+
+    fn record(path: &Path, name: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        writeln!(file, "{name} {}", std::process::id()).unwrap();
+    }
+
+    /// Returns the pids of the processes the named worker was started in.
+    fn pids(path: &Path, name: &str) -> Vec<i32> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter(|(n, _)| *n == name)
+            .map(|(_, pid)| pid.parse().unwrap())
+            .collect()
+    }
+
+    fn starts(path: &Path, name: &str) -> usize {
+        Self::pids(path, name).len()
+    }
+}
+
+impl Worker for Recorder {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Self::record(&self.path, self.name);
+        let path = self.path.clone();
+        let name = self.name;
+        let sleep = self.sleep;
+        Box::pin(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = ctx.shutdown().requested() => {
+                    Self::record(&path, &format!("{name}-stopped"));
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Recorder {
+    fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("supertrees-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_one_for_all() {
+    use supertrees::{Strategy, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("one-for-all");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_strategy(Strategy::OneForAll)
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::ZERO,
+            RestartPolicy::Once,
+        ))
+        .add_worker(Recorder::new(
+            &path,
+            "b",
+            Duration::from_millis(200),
+            RestartPolicy::Never,
+        ))
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "a"), 2);
+    assert_eq!(Recorder::starts(&path, "b"), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_one_for_all_spans_supervisors() {
+    use supertrees::{Strategy, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("one-for-all-mixed");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_strategy(Strategy::OneForAll)
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_millis(200),
+            RestartPolicy::Once,
+        ))
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::Never)
+                .add_worker(Recorder::new(
+                    &path,
+                    "b",
+                    Duration::from_secs(1),
+                    RestartPolicy::Never,
+                ))
+        })
+        .add_worker(Recorder::new(
+            &path,
+            "c",
+            Duration::from_secs(1),
+            RestartPolicy::Never,
+        ))
+        .start()
+        .unwrap();
+    // the worker's restart restarts the supervisor added after it, and the
+    // worker added after the supervisor
+    assert_eq!(Recorder::starts(&path, "a"), 2);
+    assert_eq!(Recorder::starts(&path, "b"), 2);
+    assert_eq!(Recorder::starts(&path, "b-stopped"), 1);
+    assert_eq!(Recorder::starts(&path, "c"), 2);
+    assert_eq!(Recorder::starts(&path, "c-stopped"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_rest_for_one() {
+    use supertrees::{Strategy, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("rest-for-one");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_strategy(Strategy::RestForOne)
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_secs(1),
+            RestartPolicy::Never,
+        ))
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::Once)
+                .add_worker(Recorder::new(
+                    &path,
+                    "b",
+                    Duration::from_millis(200),
+                    RestartPolicy::Never,
+                ))
+        })
+        .add_worker(Recorder::new(
+            &path,
+            "c",
+            Duration::from_secs(1),
+            RestartPolicy::Never,
+        ))
+        .start()
+        .unwrap();
+    // only the worker added after the restarted supervisor is restarted
+    // along with it
+    assert_eq!(Recorder::starts(&path, "a"), 1);
+    assert_eq!(Recorder::starts(&path, "a-stopped"), 0);
+    assert_eq!(Recorder::starts(&path, "b"), 2);
+    assert_eq!(Recorder::starts(&path, "c"), 2);
+    assert_eq!(Recorder::starts(&path, "c-stopped"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_restart_intensity() {
+    use supertrees::{RestartIntensity, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("restart-intensity");
+    let summary = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_restart_intensity(RestartIntensity::new(2, Duration::from_secs(10)))
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::ZERO,
+            RestartPolicy::Always,
+        ))
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "a"), 3);
+    assert!(summary.is_intensity_exceeded());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_restart_intensity_spans_supervisors() {
+    use supertrees::{RestartIntensity, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("restart-intensity-mixed");
+    let summary = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_restart_intensity(RestartIntensity::new(2, Duration::from_secs(10)))
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_millis(100),
+            RestartPolicy::Always,
+        ))
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::Always)
+                .add_worker(Recorder::new(
+                    &path,
+                    "b",
+                    Duration::from_millis(100),
+                    RestartPolicy::Never,
+                ))
+        })
+        .start()
+        .unwrap();
+    // the restarts of the worker and of the supervisor count against the same
+    // intensity
+    let starts = Recorder::starts(&path, "a") + Recorder::starts(&path, "b");
+    assert!(starts <= 4, "started {starts} times");
+    assert!(summary.is_intensity_exceeded());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_shutdown_reaps_subtree() {
+    use supertrees::{RestartIntensity, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("shutdown");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_restart_intensity(RestartIntensity::new(0, Duration::from_secs(10)))
+        .add_supervisor(|s| {
+            s.add_worker(Recorder::new(
+                &path,
+                "b",
+                Duration::from_secs(3600),
+                RestartPolicy::Never,
+            ))
+        })
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_millis(100),
+            RestartPolicy::Always,
+        ))
+        .start()
+        .unwrap();
+    // the long running worker has been asked to stop, and reaped before
+    // start() returned
+    let pids = Recorder::pids(&path, "b");
+    assert_eq!(pids.len(), 1);
+    assert_eq!(Recorder::starts(&path, "b-stopped"), 1);
+    assert_eq!(unsafe { libc::kill(pids[0], 0) }, -1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends its name to a file each time it's started, then panics.
+#[derive(Debug)]
+struct Panicker {
+    path: PathBuf,
+}
+
+impl Worker for Panicker {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "panicker");
+        Box::pin(async move {
+            panic!("oh no");
+        })
+    }
+}
+
+impl Restartable for Panicker {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Once
+    }
+}
+
+#[test]
+fn test_worker_panic() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("panic");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Panicker { path: path.clone() })
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_millis(300),
+            RestartPolicy::Never,
+        ))
+        .start()
+        .unwrap();
+    // the panicking worker is restarted, while its sibling keeps running
+    assert_eq!(Recorder::starts(&path, "panicker"), 2);
+    assert_eq!(Recorder::starts(&path, "a"), 1);
+    assert_eq!(Recorder::starts(&path, "a-stopped"), 0);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends its name to a file each time it's started, then panics before
+/// returning its future.
+#[derive(Debug)]
+struct InitPanicker {
+    path: PathBuf,
+}
+
+impl Worker for InitPanicker {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "init-panicker");
+        panic!("oh no");
+    }
+}
+
+impl Restartable for InitPanicker {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Once
+    }
+}
+
+#[test]
+fn test_worker_init_panic() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("init-panic");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(InitPanicker { path: path.clone() })
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_millis(300),
+            RestartPolicy::Never,
+        ))
+        .start()
+        .unwrap();
+    // a panic in init is handled like a panic in the worker's future, rather
+    // than taking down the watcher along with the sibling
+    assert_eq!(Recorder::starts(&path, "init-panicker"), 2);
+    assert_eq!(Recorder::starts(&path, "a"), 1);
+    assert_eq!(Recorder::starts(&path, "a-stopped"), 0);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends its name to a file each time it's started, then fails.
+#[derive(Debug)]
+struct Failer {
+    path: PathBuf,
+}
+
+impl Worker for Failer {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "failer");
+        Box::pin(async move { Err("oh no".into()) })
+    }
+}
+
+impl Restartable for Failer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Once
+    }
+}
+
+/// Returns how the root supervisor's watcher last stopped, as seen by the
+/// root supervisor.
+fn watcher_status(summary: &ExitSummary) -> Option<ExitStatus> {
+    summary
+        .children()
+        .find(|(path, _)| *path == "root/watcher")
+        .and_then(|(_, exit_status)| exit_status)
+}
+
+#[test]
+fn test_worker_error() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("error");
+    // the watcher exits with the exit code for the reason of the worker it
+    // gave up on, which is what its supervisor observes
+    let summary = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Failer { path: path.clone() })
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "failer"), 2);
+    assert_eq!(watcher_status(&summary), Some(ExitStatus::Exited(1)));
+    let summary = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Panicker { path: path.clone() })
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "panicker"), 2);
+    assert_eq!(watcher_status(&summary), Some(ExitStatus::Exited(101)));
+    // a watcher whose workers were asked to stop exits like a process stopped
+    // by SIGTERM, which isn't a failure
+    let mut tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::from_secs(3600),
+            RestartPolicy::Never,
+        ));
+    let handle = tree.handle().unwrap();
+    let client = std::thread::spawn(move || handle.shutdown().unwrap());
+    let summary = tree.start().unwrap();
+    client.join().unwrap();
+    assert_eq!(
+        watcher_status(&summary),
+        Some(ExitStatus::Exited(128 + libc::SIGTERM))
+    );
+    assert_eq!(summary.code(), 0);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends its name to a file each time it's started, and fails until it's
+/// been started more than `failures` times.
+#[derive(Debug)]
+struct Flaky {
+    path: PathBuf,
+    failures: usize,
+    restart_policy: RestartPolicy,
+}
+
+impl Worker for Flaky {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "flaky");
+        let fail = Recorder::starts(&self.path, "flaky") <= self.failures;
+        Box::pin(async move {
+            if fail {
+                return Err("not yet".into());
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Flaky {
+    fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+}
+
+#[test]
+fn test_transient() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("transient");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Flaky {
+            path: path.clone(),
+            failures: 2,
+            restart_policy: RestartPolicy::Transient,
+        })
+        .start()
+        .unwrap();
+    // the worker is retried until it finishes, and isn't restarted after
+    assert_eq!(Recorder::starts(&path, "flaky"), 3);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_max_retries() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("max-retries");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Flaky {
+            path: path.clone(),
+            failures: usize::MAX,
+            restart_policy: RestartPolicy::MaxRetries(2),
+        })
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "flaky"), 3);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_on_exit_codes() {
+    use supertrees::{ExitReason, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("on-exit-codes");
+    // workers that fail with an error exit with code 1
+    let codes = vec![ExitReason::Error("oh no".into()).exit_code()];
+    assert_eq!(codes, [1]);
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Flaky {
+            path: path.clone(),
+            failures: 1,
+            restart_policy: RestartPolicy::on_exit_codes(codes),
+        })
+        .start()
+        .unwrap();
+    // restarted after failing, but not after finishing normally
+    assert_eq!(Recorder::starts(&path, "flaky"), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Kills the supervisor running it with SIGKILL the first time it's started.
+#[derive(Debug)]
+struct Killer {
+    path: PathBuf,
+}
+
+impl Worker for Killer {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "killer");
+        if Recorder::starts(&self.path, "killer") == 1 {
+            // the worker runs in the watcher, whose parent is the supervisor
+            unsafe { libc::kill(libc::getppid(), libc::SIGKILL) };
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Restartable for Killer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_signaled_child_restarted() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("signaled");
+    let mut tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::Once)
+                .add_worker(Recorder::new(
+                    &path,
+                    "b",
+                    Duration::from_millis(100),
+                    RestartPolicy::Never,
+                ))
+                .add_worker(Killer { path: path.clone() })
+        });
+    let events = tree.subscribe().unwrap();
+    tree.start().unwrap();
+    // the killed supervisor is restarted rather than taking down the tree
+    assert_eq!(Recorder::starts(&path, "killer"), 2);
+    assert_eq!(Recorder::starts(&path, "b"), 2);
+    // the signal that killed it is recorded, along with whether it dumped
+    // core, which SIGKILL never does
+    let mut exits = vec![];
+    while let Some(event) = events.recv_timeout(Duration::from_millis(100)).unwrap() {
+        if let SupervisionEventKind::Exited(exit_status) = event.kind() {
+            if event.path() == "root/supervisor-0" {
+                exits.push(exit_status);
+            }
+        }
+    }
+    assert_eq!(
+        exits.first(),
+        Some(&ExitStatus::Signaled {
+            signal: libc::SIGKILL,
+            core_dumped: false,
+        })
+    );
+    assert_eq!(exits.len(), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_backoff_doesnt_block_siblings() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("backoff");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::MaxRetries(1))
+                .with_backoff_policy(BackoffPolicy::new(
+                    Duration::from_secs(1),
+                    Duration::from_secs(2),
+                    Duration::from_secs(3),
+                    1.5,
+                ))
+                .add_worker(Recorder::new(
+                    &path,
+                    "slow",
+                    Duration::ZERO,
+                    RestartPolicy::Never,
+                ))
+        })
+        .add_supervisor(|s| {
+            s.with_restart_policy(RestartPolicy::MaxRetries(3))
+                .add_worker(Recorder::new(
+                    &path,
+                    "fast",
+                    Duration::ZERO,
+                    RestartPolicy::Never,
+                ))
+        })
+        .start()
+        .unwrap();
+    // the fast supervisor is restarted while the slow one is backing off
+    let names: Vec<String> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.split_once(' ').map(|(name, _)| name.to_string()))
+        .collect();
+    assert_eq!(names.iter().filter(|name| *name == "fast").count(), 4);
+    assert_eq!(names.last().map(String::as_str), Some("slow"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_supervisor_handle() {
+    use supertrees::{HandleError, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("handle");
+    let template_path = path.clone();
+    let mut tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_template("recorder", move |_id| {
+            Recorder::new(
+                &template_path,
+                "dyn",
+                Duration::from_secs(3600),
+                RestartPolicy::Always,
+            )
+        });
+    let handle = tree.handle().unwrap();
+    // requests wait for the supervisor to start serving them, for as long as
+    // the handle's timeout allows
+    assert!(matches!(
+        handle
+            .clone()
+            .with_timeout(Some(Duration::from_millis(50)))
+            .count_children(),
+        Err(HandleError::Timeout)
+    ));
+    let client = std::thread::spawn(move || {
+        handle.start_child("recorder", "one").unwrap();
+        assert!(matches!(
+            handle.start_child("recorder", "one"),
+            Err(HandleError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            handle.start_child("missing", "two"),
+            Err(HandleError::UnknownTemplate(_))
+        ));
+        // the supervisor refuses IDs that can't be part of a worker's path
+        assert!(matches!(
+            handle.start_child("recorder", "a/b"),
+            Err(HandleError::InvalidName(id)) if id == "a/b"
+        ));
+        let children = handle.which_children().unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id(), "one");
+        assert!(children[0].is_running());
+        assert!(matches!(
+            handle.delete_child("one"),
+            Err(HandleError::Running(_))
+        ));
+
+        // a terminated child isn't restarted until it's asked to be
+        handle.terminate_child("one").unwrap();
+        let count = handle.count_children().unwrap();
+        assert_eq!((count.specs(), count.active()), (1, 0));
+        handle.restart_child("one").unwrap();
+        assert_eq!(handle.count_children().unwrap().active(), 1);
+
+        handle.terminate_child("one").unwrap();
+        handle.delete_child("one").unwrap();
+        assert!(handle.which_children().unwrap().is_empty());
+        handle.shutdown().unwrap();
+    });
+    tree.start().unwrap();
+    client.join().unwrap();
+    assert_eq!(Recorder::starts(&path, "dyn"), 2);
+    assert_eq!(Recorder::starts(&path, "dyn-stopped"), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_supervisor_handle_child_supervisors() {
+    use supertrees::{HandleError, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("handle-supervisors");
+    let mut tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker(
+            "w",
+            Recorder::new(&path, "w", Duration::from_secs(3600), RestartPolicy::Always),
+        )
+        .add_named_supervisor("sub", |s| {
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_named_worker(
+                    "inner",
+                    Recorder::new(
+                        &path,
+                        "inner",
+                        Duration::from_secs(3600),
+                        RestartPolicy::Always,
+                    ),
+                )
+        });
+    let handle = tree.handle().unwrap();
+    let client_path = path.clone();
+    let client = std::thread::spawn(move || {
+        // the supervisor is terminated once its worker has started
+        let started = |starts| {
+            while Recorder::starts(&client_path, "inner") < starts {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        // supervisors are listed along with the workers, in the order they
+        // were added
+        let children = handle.which_children().unwrap();
+        let ids: Vec<_> = children.iter().map(|child| child.id()).collect();
+        assert_eq!(ids, ["w", "sub"]);
+        assert!(children.iter().all(|child| child.is_running()));
+        assert!(matches!(
+            handle.start_child("recorder", "sub"),
+            Err(HandleError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            handle.delete_child("sub"),
+            Err(HandleError::Running(_))
+        ));
+
+        started(1);
+        handle.terminate_child("sub").unwrap();
+        let count = handle.count_children().unwrap();
+        assert_eq!((count.specs(), count.active()), (2, 1));
+        handle.restart_child("sub").unwrap();
+        assert_eq!(handle.count_children().unwrap().active(), 2);
+        assert!(matches!(
+            handle.restart_child("sub"),
+            Err(HandleError::Running(_))
+        ));
+
+        started(2);
+        handle.terminate_child("sub").unwrap();
+        handle.delete_child("sub").unwrap();
+        let children = handle.which_children().unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id(), "w");
+        handle.shutdown().unwrap();
+    });
+    tree.start().unwrap();
+    client.join().unwrap();
+    assert_eq!(Recorder::starts(&path, "inner"), 2);
+    assert_eq!(Recorder::starts(&path, "inner-stopped"), 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_dynamic_supervisor() {
+    use supertrees::{HandleError, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("dynamic");
+    let template_path = path.clone();
+    let mut handle = None;
+    let tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_dynamic_supervisor(|mut d| {
+            handle = Some(d.handle().unwrap());
+            d.with_restart_policy(RestartPolicy::Transient)
+                .with_max_children(2)
+                .with_template(move |_id| {
+                    Recorder::new(
+                        &template_path,
+                        "conn",
+                        Duration::from_secs(3600),
+                        RestartPolicy::Always,
+                    )
+                })
+        });
+    let handle = handle.unwrap();
+    let client = std::thread::spawn(move || {
+        handle.start_child("a").unwrap();
+        handle.start_child("b").unwrap();
+        assert!(matches!(
+            handle.start_child("c"),
+            Err(HandleError::MaxChildren)
+        ));
+        // terminating a child makes room for another
+        handle.terminate_child("a").unwrap();
+        handle.start_child("c").unwrap();
+        let ids: Vec<String> = handle
+            .which_children()
+            .unwrap()
+            .iter()
+            .map(|child| child.id().to_string())
+            .collect();
+        assert_eq!(ids, ["b", "c"]);
+        handle.shutdown().unwrap();
+    });
+    tree.start().unwrap();
+    client.join().unwrap();
+    assert_eq!(Recorder::starts(&path, "conn"), 3);
+    assert_eq!(Recorder::starts(&path, "conn-stopped"), 3);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends the path it's started with, and the title of the process it runs
+/// in, to a file, then stops.
+#[derive(Debug)]
+struct PathRecorder {
+    path: PathBuf,
+}
+
+impl Worker for PathRecorder {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let title = std::fs::read_to_string("/proc/self/comm").unwrap_or_default();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .unwrap();
+        writeln!(file, "{} {}", ctx.path(), title.trim()).unwrap();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Restartable for PathRecorder {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_named_children() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("named");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(PathRecorder { path: path.clone() })
+        .add_named_supervisor("db", |s| {
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_named_worker("pool", PathRecorder { path: path.clone() })
+                .add_named_worker("worker-2", PathRecorder { path: path.clone() })
+                .add_worker(PathRecorder { path: path.clone() })
+        })
+        .start()
+        .unwrap();
+    // a worker added without a name skips the names already taken
+    let mut lines: Vec<String> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    if cfg!(target_os = "linux") {
+        assert_eq!(
+            lines,
+            [
+                "root/db/pool root/db/watcher",
+                "root/db/worker-2 root/db/watcher",
+                "root/db/worker-3 root/db/watcher",
+                "root/worker-0 root/watcher"
+            ]
+        );
+    } else {
+        let paths: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line.split(' ').next())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "root/db/pool",
+                "root/db/worker-2",
+                "root/db/worker-3",
+                "root/worker-0"
+            ]
+        );
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+#[should_panic(expected = "duplicate child name")]
+fn test_duplicate_child_name() {
+    supertrees::Supertree::new()
+        .add_named_worker("ingest", W::new(1))
+        .add_named_worker("ingest", W::new(2));
+}
+
+#[test]
+#[should_panic(expected = "reserved for the supervisor's watcher")]
+fn test_reserved_child_name() {
+    supertrees::Supertree::new().add_named_worker("watcher", W::new(1));
+}
+
+/// Registers how many times it's been started under its name, failing the
+/// first time so that it's restarted.
+#[derive(Debug)]
+struct Registrant {
+    path: PathBuf,
+}
+
+impl Worker for Registrant {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "registrant");
+        let instance = Recorder::starts(&self.path, "registrant");
+        ctx.register("registrant", instance).unwrap();
+        Box::pin(async move {
+            if instance == 1 {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                return Err("first instance".into());
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Registrant {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Transient
+    }
+}
+
+/// Looks up the registrant by name until it finds the restarted instance.
+#[derive(Debug)]
+struct Looker {
+    path: PathBuf,
+}
+
+impl Worker for Looker {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        Box::pin(async move {
+            let registry = ctx.registry();
+            for _ in 0..500 {
+                if registry.lookup::<usize>("registrant") == Some(2) {
+                    if registry.whereis("registrant").as_deref() == Some("root/registrant") {
+                        Recorder::record(&path, "looker-found");
+                    }
+                    if ctx.register("registrant", 0usize).is_err() {
+                        Recorder::record(&path, "looker-rejected");
+                    }
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Looker {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_registry() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("registry");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker("registrant", Registrant { path: path.clone() })
+        .add_named_worker("looker", Looker { path: path.clone() })
+        .start()
+        .unwrap();
+    // the registration moves to the restarted instance, and can't be taken
+    // over by another worker while it's held
+    assert_eq!(Recorder::starts(&path, "registrant"), 2);
+    assert_eq!(Recorder::starts(&path, "looker-found"), 1);
+    assert_eq!(Recorder::starts(&path, "looker-rejected"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Registers how many times it's been started under its name, failing the
+/// first time so that it backs off for half a second before it's restarted.
+#[derive(Debug)]
+struct Backer {
+    path: PathBuf,
+}
+
+impl Worker for Backer {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "backer");
+        let instance = Recorder::starts(&self.path, "backer");
+        ctx.register("backer", instance).unwrap();
+        Box::pin(async move {
+            if instance == 1 {
+                return Err("first instance".into());
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Backer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Transient
+    }
+
+    fn backoff_policy(&self) -> BackoffPolicy {
+        BackoffPolicy::new(
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+            1.5,
+        )
+    }
+}
+
+/// Looks up the backer by name while it's backing off, and again once it has
+/// been restarted.
+#[derive(Debug)]
+struct BackoffLooker {
+    path: PathBuf,
+}
+
+impl Worker for BackoffLooker {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        Box::pin(async move {
+            let registry = ctx.registry();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            if registry.lookup::<usize>("backer").is_none() {
+                Recorder::record(&path, "looker-backing-off");
+            }
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            if registry.lookup::<usize>("backer") == Some(2) {
+                Recorder::record(&path, "looker-restarted");
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for BackoffLooker {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_registry_during_backoff() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("registry-backoff");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker("backer", Backer { path: path.clone() })
+        .add_named_worker("looker", BackoffLooker { path: path.clone() })
+        .start()
+        .unwrap();
+    // the restarted instance doesn't register its name until it's done
+    // backing off
+    assert_eq!(Recorder::starts(&path, "backer"), 2);
+    assert_eq!(Recorder::starts(&path, "looker-backing-off"), 1);
+    assert_eq!(Recorder::starts(&path, "looker-restarted"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Records the messages it receives, failing on the first one so that it's
+/// restarted, and stopping after the last one.
+#[derive(Debug)]
+struct Consumer {
+    path: PathBuf,
+    mailbox: Mailbox<u32>,
+}
+
+impl Worker for Consumer {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        let mailbox = self.mailbox.clone();
+        Box::pin(async move {
+            while let Some(message) = mailbox.recv().await {
+                Recorder::record(&path, &format!("got-{message}"));
+                match message {
+                    1 => return Err("first message".into()),
+                    3 => break,
+                    _ => {}
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Consumer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Transient
+    }
+}
+
+/// Sends messages to the consumer, the rest of them while it's restarting.
+#[derive(Debug)]
+struct Producer {
+    sender: MailboxSender<u32>,
+}
+
+impl Worker for Producer {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let sender = self.sender.clone();
+        Box::pin(async move {
+            sender.send(1)?;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            sender.send(2)?;
+            sender.send(3)?;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Producer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_mailbox_survives_restart() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("mailbox");
+    let mailbox = Mailbox::new(8);
+    let sender = mailbox.sender();
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Consumer {
+            path: path.clone(),
+            mailbox,
+        })
+        .add_worker(Producer { sender })
+        .start()
+        .unwrap();
+    // messages sent while the consumer was restarting are received by the
+    // restarted instance
+    for message in ["got-1", "got-2", "got-3"] {
+        assert_eq!(Recorder::starts(&path, message), 1);
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_mailbox_overflow() {
+    let mailbox = Mailbox::new(2);
+    let sender = mailbox.sender();
+    sender.send(1).unwrap();
+    sender.send(2).unwrap();
+    assert_eq!(sender.send(3), Err(SendError::Full(3)));
+
+    let mailbox = Mailbox::new(2).with_overflow_policy(OverflowPolicy::DropOldest);
+    let sender = mailbox.sender();
+    (1..=3).for_each(|message| sender.send(message).unwrap());
+    assert_eq!(mailbox.try_recv(), Some(2));
+    assert_eq!(mailbox.try_recv(), Some(3));
+    assert_eq!(mailbox.try_recv(), None);
+    drop(mailbox);
+    assert_eq!(sender.send(4), Err(SendError::Closed(4)));
+}
+
+/// Sends strings between processes as UTF-8.
+#[derive(Debug, Clone, Copy)]
+struct Utf8Codec;
+
+impl Codec<String> for Utf8Codec {
+    fn encode(&self, message: &String) -> Result<Vec<u8>, BoxError> {
+        Ok(message.as_bytes().to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, BoxError> {
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+/// Sends a greeting to a worker in another supervisor.
+#[derive(Debug)]
+struct Greeter {
+    sender: IpcSender<String, Utf8Codec>,
+}
+
+impl Worker for Greeter {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let sender = self.sender.clone();
+        Box::pin(async move {
+            sender.send(&"hello".to_string())?;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Greeter {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+/// Records the first message it receives from another process.
+#[derive(Debug)]
+struct Listener {
+    path: PathBuf,
+}
+
+impl Worker for Listener {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        let receiver = ctx.receiver(Utf8Codec);
+        Box::pin(async move {
+            let message = tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await??;
+            Recorder::record(&path, &format!("received-{message}"));
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Listener {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_ipc_between_subtrees() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("ipc");
+    let sender = IpcSender::new("root/b/listener", Utf8Codec);
+    // only the tree's workers are linked to it
+    assert!(matches!(
+        sender.send(&"hello".to_string()),
+        Err(IpcError::Disconnected)
+    ));
+    // the destinations of the tree's own frames can't be sent to by workers
+    assert!(matches!(
+        IpcSender::new("\trestart", Utf8Codec).send(&"hello".to_string()),
+        Err(IpcError::InvalidDestination(_))
+    ));
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_supervisor("a", |s| {
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_named_worker("greeter", Greeter { sender })
+        })
+        .add_named_supervisor("b", |s| {
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_named_worker("listener", Listener { path: path.clone() })
+        })
+        .start()
+        .unwrap();
+    assert_eq!(Recorder::starts(&path, "received-hello"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Counts increments, failing when asked to so that it's restarted.
+#[derive(Debug)]
+struct Counter {
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+enum CounterCall {
+    Get,
+    Fail,
+}
+
+impl GenServer for Counter {
+    type Call = CounterCall;
+    type Cast = usize;
+    type Info = ();
+    type Reply = usize;
+    type State = usize;
+
+    async fn init(&self, _ctx: &Context) -> Result<usize, BoxError> {
+        Recorder::record(&self.path, "counter-init");
+        Ok(0)
+    }
+
+    async fn handle_call(
+        &self,
+        request: CounterCall,
+        state: &mut usize,
+    ) -> Result<usize, BoxError> {
+        match request {
+            CounterCall::Get => Ok(*state),
+            CounterCall::Fail => Err("asked to fail".into()),
+        }
+    }
+
+    async fn handle_cast(&self, message: usize, state: &mut usize) -> Result<(), BoxError> {
+        *state += message;
+        Ok(())
+    }
+
+    async fn terminate(&self, reason: &ExitReason, _state: usize) {
+        Recorder::record(
+            &self.path,
+            &format!("counter-terminate-{}", reason.is_abnormal()),
+        );
+    }
+}
+
+impl Restartable for Counter {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Always
+    }
+}
+
+/// Calls the counter, then shuts the tree down.
+#[derive(Debug)]
+struct CounterClient {
+    path: PathBuf,
+    client: GenServerClient<Counter>,
+    handle: SupervisorHandle,
+}
+
+impl Worker for CounterClient {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        let client = self.client.clone();
+        let handle = self.handle.clone();
+        Box::pin(async move {
+            let timeout = Duration::from_secs(5);
+            client.cast(2)?;
+            client.cast(3)?;
+            let count = client.call(CounterCall::Get, timeout).await?;
+            Recorder::record(&path, &format!("count-{count}"));
+            if client.call(CounterCall::Fail, timeout).await == Err(GenServerError::NoReply) {
+                Recorder::record(&path, "no-reply");
+            }
+            // the call waits in the mailbox until the counter is restarted
+            let count = client.call(CounterCall::Get, timeout).await?;
+            Recorder::record(&path, &format!("count-{count}"));
+            tokio::task::spawn_blocking(move || handle.shutdown()).await??;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for CounterClient {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_gen_server() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("gen-server");
+    let server = GenServerWorker::new(Counter { path: path.clone() });
+    let client = server.client();
+    let mut tree = Supertree::new().with_thread_policy(ThreadPolicy::Allow);
+    let handle = tree.handle().unwrap();
+    tree.add_named_worker("counter", server)
+        .add_named_worker(
+            "client",
+            CounterClient {
+                path: path.clone(),
+                client,
+                handle,
+            },
+        )
+        .start()
+        .unwrap();
+    // the state is rebuilt by init after the counter is restarted
+    assert_eq!(Recorder::starts(&path, "count-5"), 1);
+    assert_eq!(Recorder::starts(&path, "no-reply"), 1);
+    assert_eq!(Recorder::starts(&path, "count-0"), 1);
+    assert_eq!(Recorder::starts(&path, "counter-init"), 2);
+    assert_eq!(Recorder::starts(&path, "counter-terminate-true"), 1);
+    assert_eq!(Recorder::starts(&path, "counter-terminate-false"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Fails the first time it's started, and finishes the second time.
+#[derive(Debug)]
+struct Dependency {
+    path: PathBuf,
+}
+
+impl Worker for Dependency {
+    fn init(
+        &self,
+        _ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "dependency");
+        let first = Recorder::starts(&self.path, "dependency") == 1;
+        Box::pin(async move {
+            if first {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                return Err("dependency failed".into());
+            }
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Dependency {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Transient
+    }
+}
+
+/// Links to the dependency the first time it's started, and monitors it the
+/// second time.
+#[derive(Debug)]
+struct Dependent {
+    path: PathBuf,
+}
+
+impl Worker for Dependent {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        Recorder::record(&self.path, "dependent");
+        let first = Recorder::starts(&self.path, "dependent") == 1;
+        let path = self.path.clone();
+        Box::pin(async move {
+            if first {
+                ctx.link("dependency")?;
+                std::future::pending::<()>().await;
+            }
+            let monitor = loop {
+                match ctx.monitor("dependency") {
+                    Ok(monitor) => break monitor,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+            let down = monitor.down().await.ok_or("no down message")?;
+            Recorder::record(
+                &path,
+                &format!("dependent-down-{}", down.reason().is_abnormal()),
+            );
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Dependent {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Transient
+    }
+}
+
+/// Records how the dependency first stops.
+#[derive(Debug)]
+struct Monitorer {
+    path: PathBuf,
+}
+
+impl Worker for Monitorer {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        let monitor = ctx.monitor("dependency");
+        Box::pin(async move {
+            let down = monitor?.down().await.ok_or("no down message")?;
+            Recorder::record(
+                &path,
+                &format!("{}-down-{}", down.id(), down.reason().is_abnormal()),
+            );
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for Monitorer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_links_and_monitors() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("links");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker("dependency", Dependency { path: path.clone() })
+        .add_named_worker("dependent", Dependent { path: path.clone() })
+        .add_named_worker("monitorer", Monitorer { path: path.clone() })
+        .start()
+        .unwrap();
+    // the linked worker fails along with the dependency, and both are
+    // restarted
+    assert_eq!(Recorder::starts(&path, "dependency"), 2);
+    assert_eq!(Recorder::starts(&path, "dependent"), 2);
+    assert_eq!(Recorder::starts(&path, "dependency-down-true"), 1);
+    assert_eq!(Recorder::starts(&path, "dependent-down-false"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Monitors the backer while it's backing off, and again once it has been
+/// restarted.
+#[derive(Debug)]
+struct BackoffMonitorer {
+    path: PathBuf,
+}
+
+impl Worker for BackoffMonitorer {
+    fn init(
+        &self,
+        ctx: Context,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), BoxError>> + Send + 'static>>
+    {
+        let path = self.path.clone();
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            if let Err(LinkError::NotRunning(_)) = ctx.monitor("backer") {
+                Recorder::record(&path, "monitorer-backing-off");
+            }
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            let down = ctx
+                .monitor("backer")?
+                .down()
+                .await
+                .ok_or("no down message")?;
+            Recorder::record(
+                &path,
+                &format!("{}-down-{}", down.id(), down.reason().is_abnormal()),
+            );
+            Ok(())
+        })
+    }
+}
+
+impl Restartable for BackoffMonitorer {
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[test]
+fn test_links_during_backoff() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("links-backoff");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker("backer", Backer { path: path.clone() })
+        .add_named_worker("monitorer", BackoffMonitorer { path: path.clone() })
+        .start()
+        .unwrap();
+    // the restarted instance isn't running until it's done backing off, and
+    // only then can it be monitored
+    assert_eq!(Recorder::starts(&path, "backer"), 2);
+    assert_eq!(Recorder::starts(&path, "monitorer-backing-off"), 1);
+    assert_eq!(Recorder::starts(&path, "backer-down-false"), 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Appends the lifecycle events of a tree's children to a file, as they're
+/// observed from the processes that run their supervisors.
+struct EventRecorder {
+    path: PathBuf,
+}
+
+impl SupervisorObserver for EventRecorder {
+    fn on_start(&self, path: &str) {
+        Recorder::record(&self.path, &format!("start:{path}"));
+    }
+
+    fn on_exit(&self, path: &str, exit: &ChildExit) {
+        Recorder::record(&self.path, &format!("exit:{path}:{}", exit.is_abnormal()));
+    }
+
+    fn on_restart(&self, path: &str, _delay: Duration) {
+        Recorder::record(&self.path, &format!("restart:{path}"));
+    }
+
+    fn on_give_up(&self, path: &str, _exit: &ChildExit) {
+        Recorder::record(&self.path, &format!("give-up:{path}"));
+    }
+}
+
+#[test]
+fn test_observer() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("observer");
+    Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .with_observer(EventRecorder { path: path.clone() })
+        .add_named_worker(
+            "flaky",
+            Flaky {
+                path: path.clone(),
+                failures: 1,
+                restart_policy: RestartPolicy::Transient,
+            },
+        )
+        .add_named_supervisor("db", |s| {
+            s.with_restart_policy(RestartPolicy::Never)
+                .add_named_worker("failer", Failer { path: path.clone() })
+        })
+        .start()
+        .unwrap();
+    // the flaky worker fails once, is restarted and then finishes
+    assert_eq!(Recorder::starts(&path, "start:root/flaky"), 2);
+    assert_eq!(Recorder::starts(&path, "exit:root/flaky:true"), 1);
+    assert_eq!(Recorder::starts(&path, "exit:root/flaky:false"), 1);
+    assert_eq!(Recorder::starts(&path, "restart:root/flaky"), 1);
+    assert_eq!(Recorder::starts(&path, "give-up:root/flaky"), 0);
+    // the child supervisor inherits the observer, and gives up on its worker
+    // once it has been restarted
+    assert_eq!(Recorder::starts(&path, "start:root/db/failer"), 2);
+    assert_eq!(Recorder::starts(&path, "restart:root/db/failer"), 1);
+    assert_eq!(Recorder::starts(&path, "give-up:root/db/failer"), 1);
+    // child processes are observed too
+    assert!(Recorder::starts(&path, "start:root/db") >= 1);
+    assert!(Recorder::starts(&path, "start:root/watcher") >= 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_event_stream() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("events");
+    let mut tree = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_named_worker(
+            "flaky",
+            Flaky {
+                path: path.clone(),
+                failures: 1,
+                restart_policy: RestartPolicy::Transient,
+            },
+        )
+        .add_named_supervisor("db", |s| {
+            s.with_restart_policy(RestartPolicy::Never)
+                .add_named_worker("failer", Failer { path: path.clone() })
+        });
+    let events = tree.subscribe().unwrap();
+    tree.start().unwrap();
+    let mut received: Vec<SupervisionEvent> = vec![];
+    while let Some(event) = events.recv_timeout(Duration::from_millis(100)).unwrap() {
+        received.push(event);
+    }
+    let count = |path: &str, kind: SupervisionEventKind| {
+        received
+            .iter()
+            .filter(|event| event.path() == path && event.kind() == kind)
+            .count()
+    };
+    assert_eq!(count("root/flaky", SupervisionEventKind::Started), 2);
+    assert_eq!(
+        count(
+            "root/flaky",
+            SupervisionEventKind::Exited(ExitStatus::Exited(1))
+        ),
+        1
+    );
+    assert_eq!(
+        count(
+            "root/flaky",
+            SupervisionEventKind::Exited(ExitStatus::Exited(0))
+        ),
+        1
+    );
+    // events from the grandchild supervisor's watcher are forwarded to the
+    // root
+    assert_eq!(count("root/db/failer", SupervisionEventKind::Started), 2);
+    assert_eq!(
+        count(
+            "root/db/failer",
+            SupervisionEventKind::GaveUp(ExitStatus::Exited(1))
+        ),
+        1
+    );
+    let restart = received
+        .iter()
+        .find(|event| {
+            event.path() == "root/flaky"
+                && matches!(event.kind(), SupervisionEventKind::RestartScheduled(_))
+        })
+        .unwrap();
+    assert!(restart.restart_at().unwrap() >= restart.time());
+    // workers are reported with the pid of their watcher process, and child
+    // processes with their own
+    let db = received
+        .iter()
+        .find(|event| event.path() == "root/db")
+        .unwrap();
+    assert_ne!(db.pid(), std::process::id());
+    assert!(
+        received
+            .iter()
+            .filter(|event| event.path().starts_with("root/db/"))
+            .all(|event| event.pid() != db.pid())
+    );
+    // a short wait from another thread doesn't cut a longer one short
+    let started = std::time::Instant::now();
+    std::thread::scope(|scope| {
+        let waiter = scope.spawn(|| events.recv_timeout(Duration::from_millis(300)));
+        while !waiter.is_finished() && started.elapsed() < Duration::from_millis(200) {
+            assert!(events.recv_timeout(Duration::ZERO).unwrap().is_none());
+            assert!(
+                events
+                    .recv_timeout(Duration::from_millis(1))
+                    .unwrap()
+                    .is_none()
+            );
+        }
+        assert!(waiter.join().unwrap().unwrap().is_none());
+    });
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_exit_summary() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("exit-summary");
+    let summary = Supertree::new()
+        .with_thread_policy(ThreadPolicy::Allow)
+        .add_worker(Flaky {
+            path: path.clone(),
+            failures: 1,
+            restart_policy: RestartPolicy::Transient,
+        })
+        .add_named_supervisor("db", |s| {
+            s.with_restart_policy(RestartPolicy::Never)
+                .add_named_worker("failer", Failer { path: path.clone() })
+        })
+        .start()
+        .unwrap();
+    // the child supervisor gives up on its failing worker, and then fails,
+    // and so does the root supervisor, which gives up on it in turn
+    assert_eq!(summary.code(), 1);
+    assert!(!summary.is_shutdown() && !summary.is_intensity_exceeded());
+    assert!(summary.errors().is_empty());
+    assert_eq!(
+        summary.children().collect::<Vec<_>>(),
+        [
+            ("root/watcher", Some(ExitStatus::Exited(0))),
+            ("root/db", Some(ExitStatus::Exited(1))),
+        ]
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_refuses_threads() {
+    use supertrees::{Backend, Supertree, SupertreeError};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("refuses-threads");
+    // the test runs in a thread of its own, but another one is started in
+    // case the harness runs it on the main thread
+    let (done, wait) = std::sync::mpsc::channel::<()>();
+    let thread = std::thread::spawn(move || wait.recv());
+    let result = Supertree::new()
+        .add_worker(Recorder::new(
+            &path,
+            "a",
+            Duration::ZERO,
+            RestartPolicy::Never,
+        ))
+        .start();
+    // a supervisor that forks is refused too when it runs in a thread
+    let forking = Supertree::new()
+        .with_backend(Backend::Thread)
+        .add_supervisor(|s| {
+            s.with_backend(Backend::Fork).add_worker(Recorder::new(
+                &path,
+                "b",
+                Duration::ZERO,
+                RestartPolicy::Never,
+            ))
+        })
+        .start();
+    drop(done);
+    let _ = thread.join();
+    match result {
+        Err(SupertreeError::Multithreaded(path, threads)) => {
+            assert_eq!(path, "root");
+            assert!(threads > 1);
+        }
+        result => panic!("expected the tree to be refused, got {result:?}"),
+    }
+    assert!(matches!(forking, Err(SupertreeError::Multithreaded(..))));
+    // no children were started
+    assert_eq!(Recorder::starts(&path, "a"), 0);
+    assert_eq!(Recorder::starts(&path, "b"), 0);
+}
+
+#[test]
+fn test_thread_backend() {
+    use supertrees::{Backend, RestartIntensity, Supertree};
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("thread-backend");
+    let db_path = path.clone();
+    // the tree is started from the test's thread, without forking
+    let summary = Supertree::new()
+        .with_backend(Backend::Thread)
+        .add_named_worker(
+            "a",
+            Recorder::new(&path, "a", Duration::ZERO, RestartPolicy::Never),
+        )
+        .add_named_supervisor("db", |s| {
+            // the supervisor gives up on its worker's second failure, and is
+            // restarted by the root supervisor
+            s.with_restart_policy(RestartPolicy::Transient)
+                .with_restart_intensity(RestartIntensity::new(1, Duration::from_secs(10)))
+                .add_named_worker(
+                    "flaky",
+                    Flaky {
+                        path: db_path.clone(),
+                        failures: 2,
+                        restart_policy: RestartPolicy::Transient,
+                    },
+                )
+        })
+        .start()
+        .unwrap();
+    assert_eq!(summary.code(), 0);
+    assert_eq!(
+        summary.children().collect::<Vec<_>>(),
+        [
+            ("root/watcher", Some(ExitStatus::Exited(0))),
+            ("root/db", Some(ExitStatus::Exited(0))),
+        ]
+    );
+    // the restarted supervisor runs the worker it was built with again
+    assert_eq!(Recorder::starts(&path, "flaky"), 3);
+    assert_eq!(Recorder::starts(&path, "a"), 1);
+    let pid = std::process::id() as i32;
+    assert!(
+        Recorder::pids(&path, "flaky")
+            .into_iter()
+            .chain(Recorder::pids(&path, "a"))
+            .all(|worker_pid| worker_pid == pid)
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_spawn() {
+    use supertrees::Supertree;
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("spawn");
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let summary = rt.block_on(async {
+        // the tree runs on the test's runtime, in threads of the test's
+        // process
+        let handle = Supertree::new()
+            .add_named_worker(
+                "idle",
+                Recorder::new(
+                    &path,
+                    "idle",
+                    Duration::from_secs(3600),
+                    RestartPolicy::Never,
+                ),
+            )
+            .add_named_supervisor("a", |s| {
+                s.with_restart_policy(RestartPolicy::Transient)
+                    .add_named_worker(
+                        "greeter",
+                        Greeter {
+                            sender: IpcSender::new("root/b/listener", Utf8Codec),
+                        },
+                    )
+            })
+            .add_named_supervisor("b", |s| {
+                s.with_restart_policy(RestartPolicy::Transient)
+                    .add_named_worker("listener", Listener { path: path.clone() })
+            })
+            .spawn();
+        for _ in 0..100 {
+            if Recorder::starts(&path, "received-hello") > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        handle.shutdown().await.unwrap()
+    });
+    assert!(summary.is_shutdown());
+    assert_eq!(Recorder::starts(&path, "received-hello"), 1);
+    assert_eq!(Recorder::starts(&path, "idle-stopped"), 1);
+    let pid = std::process::id() as i32;
+    assert_eq!(Recorder::pids(&path, "idle"), [pid]);
+    assert_eq!(Recorder::pids(&path, "received-hello"), [pid]);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Runs a tree with the re-exec backend, in a process started by
+/// `test_reexec`, since each of the tree's processes runs the test again.
+#[test]
+#[ignore = "run by test_reexec"]
+fn reexec_tree() {
+    use supertrees::{Backend, Supertree};
+    let path = PathBuf::from(std::env::var("SUPERTREES_TEST_PATH").unwrap());
+    // other threads don't stop a tree whose children don't inherit them
+    let (done, wait) = std::sync::mpsc::channel::<()>();
+    let thread = std::thread::spawn(move || wait.recv());
+    let db_path = path.clone();
+    let summary = Supertree::new()
+        .with_backend(Backend::ReExec)
+        .add_named_worker(
+            "flaky",
+            Flaky {
+                path: path.clone(),
+                failures: 1,
+                restart_policy: RestartPolicy::Transient,
+            },
+        )
+        .add_named_supervisor("db", |s| {
+            // each process rebuilds the tree
+            Recorder::record(&db_path, "build");
+            s.with_restart_policy(RestartPolicy::Transient)
+                .add_named_worker(
+                    "a",
+                    Recorder::new(&db_path, "a", Duration::ZERO, RestartPolicy::Never),
+                )
+        })
+        .start()
+        .unwrap();
+    drop(done);
+    let _ = thread.join();
+    Recorder::record(&path, &format!("summary-{}", summary.code()));
+}
+
+#[test]
+fn test_reexec() {
+    let _lock = TREE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("reexec");
+    let status = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([
+            "reexec_tree",
+            "--exact",
+            "--ignored",
+            "--test-threads=1",
+            "--quiet",
+        ])
+        .env("SUPERTREES_TEST_PATH", &path)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(Recorder::starts(&path, "flaky"), 2);
+    assert_eq!(Recorder::starts(&path, "a"), 1);
+    // the root, the supervisor of db and the watchers running their workers
+    assert_eq!(Recorder::starts(&path, "build"), 4);
+    assert_eq!(Recorder::starts(&path, "summary-0"), 1);
+    let _ = std::fs::remove_file(&path);
+}